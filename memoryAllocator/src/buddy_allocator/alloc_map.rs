@@ -0,0 +1,153 @@
+use core::mem::size_of;
+use core::ops::Range;
+
+use super::MIN_BLOCK_SIZE;
+
+/// Maximum number of regions a `Heap` can track with the `debug_checks` feature. Frees into a
+/// heap with more regions are not checked against the region bounds.
+pub const MAX_CHECKED_REGIONS: usize = 8;
+
+const BITS: usize = usize::BITS as usize;
+
+/// Two bits per `MIN_BLOCK_SIZE` granule of a region of a `Heap`: whether the granule is
+/// allocated, and whether an allocation starts there. Together they tell a double free from a
+/// free with the wrong layout. Blocks of adjacent regions can merge, so every method takes a
+/// block that may stick out of the map and only looks at the part inside it.
+pub struct AllocMap {
+    start: usize,
+    end: usize,
+    bits: &'static mut [usize],
+    // 起始位在`bits`中的起始位置(以word为单位)
+    starts: usize,
+}
+
+impl AllocMap {
+    /// Creates a cleared map for `[start, end)` on top of `bits`.
+    pub(crate) fn new(start: usize, end: usize, bits: &'static mut [usize]) -> Self {
+        let needed = Self::words_needed(start, end);
+        assert!(
+            bits.len() >= needed,
+            "allocation map storage too small: {} words given, {} needed",
+            bits.len(),
+            needed
+        );
+        bits.fill(0);
+        let (start, end) = Self::align(start, end);
+        AllocMap {
+            start,
+            end,
+            bits,
+            starts: needed / 2,
+        }
+    }
+
+    /// Returns the number of words needed to track `[start, end)`.
+    pub const fn words_needed(start: usize, end: usize) -> usize {
+        let (start, end) = Self::align(start, end);
+        if start >= end {
+            return 0;
+        }
+        2 * ((end - start) / MIN_BLOCK_SIZE).div_ceil(BITS)
+    }
+
+    /// Returns the number of bytes needed to track `[start, end)`.
+    pub const fn bytes_needed(start: usize, end: usize) -> usize {
+        Self::words_needed(start, end) * size_of::<usize>()
+    }
+
+    // 与`Heap::free_heap`相同的对齐方式
+    const fn align(start: usize, end: usize) -> (usize, usize) {
+        (
+            (start + MIN_BLOCK_SIZE - 1) & !(MIN_BLOCK_SIZE - 1),
+            end & !(MIN_BLOCK_SIZE - 1),
+        )
+    }
+
+    /// Returns the range the map covers.
+    pub(crate) fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    /// Returns true if `addr` is inside the map.
+    pub(crate) fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Returns true if `[addr, addr + size)` shares at least one granule with the map.
+    pub(crate) fn overlaps(&self, addr: usize, size: usize) -> bool {
+        addr < self.end && self.start < addr + size
+    }
+
+    fn locate(&self, addr: usize) -> (usize, usize) {
+        let granule = (addr - self.start) / MIN_BLOCK_SIZE;
+        (granule / BITS, 1 << (granule % BITS))
+    }
+
+    fn in_use(&self, addr: usize) -> bool {
+        let (word, mask) = self.locate(addr);
+        self.bits[word] & mask != 0
+    }
+
+    /// Returns true if an allocation starts at `addr`.
+    pub(crate) fn is_start(&self, addr: usize) -> bool {
+        let (word, mask) = self.locate(addr);
+        self.bits[self.starts + word] & mask != 0
+    }
+
+    fn set(&mut self, range: Range<usize>, in_use: bool) {
+        let range = range.start.max(self.start)..range.end.min(self.end);
+        for addr in range.step_by(MIN_BLOCK_SIZE) {
+            let (word, mask) = self.locate(addr);
+            if in_use {
+                self.bits[word] |= mask;
+            } else {
+                self.bits[word] &= !mask;
+            }
+        }
+    }
+
+    /// Records an allocation of `size` bytes at `addr`.
+    pub(crate) fn mark(&mut self, addr: usize, size: usize) {
+        self.set(addr..addr + size, true);
+        if self.contains(addr) {
+            let (word, mask) = self.locate(addr);
+            self.bits[self.starts + word] |= mask;
+        }
+    }
+
+    /// Changes the size of the allocation at `addr`.
+    pub(crate) fn resize(&mut self, addr: usize, size: usize, new_size: usize) {
+        if new_size < size {
+            self.set(addr + new_size..addr + size, false);
+        } else {
+            self.set(addr + size..addr + new_size, true);
+        }
+    }
+
+    /// Forgets the allocation of `size` bytes at `addr`.
+    pub(crate) fn unmark(&mut self, addr: usize, size: usize) {
+        self.set(addr..addr + size, false);
+        if self.contains(addr) {
+            let (word, mask) = self.locate(addr);
+            self.bits[self.starts + word] &= !mask;
+        }
+    }
+
+    /// Returns true if the allocation starting at `addr` is exactly `size` bytes, as far as
+    /// the map can tell: every granule of the block after the first belongs to it, and the
+    /// granule after the block does not.
+    pub(crate) fn has_size(&self, addr: usize, size: usize) -> bool {
+        let end = addr + size;
+        let first = (addr + MIN_BLOCK_SIZE).max(self.start);
+        let inside = (first..end.min(self.end))
+            .step_by(MIN_BLOCK_SIZE)
+            .all(|granule| self.in_use(granule) && !self.is_start(granule));
+        inside && (!self.contains(end) || !self.in_use(end) || self.is_start(end))
+    }
+}
+
+/// A region added to a `Heap`, and its allocation map if it has one.
+pub(crate) struct CheckedRegion {
+    pub(crate) range: Range<usize>,
+    pub(crate) map: Option<AllocMap>,
+}
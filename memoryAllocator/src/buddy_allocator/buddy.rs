@@ -1,6 +1,10 @@
-use std::collections::BTreeSet;
 use super::prev_power_of_two;
-use alloc::collections::BTreeSet;
+use super::frame_sets::{BitmapFrameSets, DefaultFrameSets, FrameSets, FRAME_ORDERS};
+#[cfg(feature = "alloc")]
+use super::frame_sets::BTreeFrameSets;
+use super::flags::AllocFlags;
+use super::watermark::{WatermarkCallback, Watermarks};
+use crate::fragmentation::FragmentationReport;
 use core::cmp::min;
 use core::ops::Range;
 
@@ -9,29 +13,96 @@ use core::ops::Deref;
 #[cfg(feature = "use_spin")]
 use spin::Mutex;
 
+/// Called as `migrate(from, to, count)` to ask the owner of the `count` frames at `from` to
+/// copy them to `to` and update every reference to them. Returns false if the frames cannot
+/// move right now.
+pub type MigrateCallback = fn(usize, usize, usize) -> bool;
 
-pub struct BuddyAllocator{
-    //最大支持2^32次方
-    link_list:[BTreeSet<usize>;32],
+/// What a call to [`BuddyAllocator::compact`] achieved.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactResult {
+    /// Movable blocks that were moved.
+    pub migrated: usize,
+    /// Movable blocks whose owner refused to move them.
+    pub failed: usize,
+    /// Free blocks of the requested order gained, counting a free block of a higher order as
+    /// as many blocks of the requested order as it holds.
+    pub created: usize,
+}
+
+/// A buddy allocator for frame numbers. The per-order free sets live in `S`, which is
+/// [`DefaultFrameSets`] unless named: a `BTreeSet` per order, or a [`BitmapFrameSets`] in
+/// caller-supplied storage when no heap is available yet.
+pub struct BuddyAllocator<S: FrameSets = DefaultFrameSets>{
+    link_list: S,
 
     allocated:usize, //已经分配
+    user: usize, //调用者请求的页帧数
     sum :usize,
+    watermarks: Watermarks,
+    // 分配失败时的回调
+    on_failure: Option<fn(usize)>,
+    // 可移动的已分配块,以及迁移回调
+    movable: Option<S>,
+    migrate: Option<MigrateCallback>,
 }
 
+#[cfg(feature = "alloc")]
 impl BuddyAllocator {
     //创建一个空的伙伴分配器
     pub fn new() -> Self{
-        BuddyAllocator { 
-            link_list:Default::default(), 
-            allocated: 0, 
+        Self::with_storage(BTreeFrameSets::default())
+    }
+}
+
+impl <'a> BuddyAllocator<BitmapFrameSets<'a>> {
+    /// Creates an allocator that has no storage yet, e.g. for a `static`. Replace it with
+    /// [`BuddyAllocator::with_storage`] before inserting frames.
+    pub const fn empty() -> Self{
+        BuddyAllocator {
+            link_list: BitmapFrameSets::empty(),
+            allocated: 0,
+            user: 0,
             sum: 0,
-         }
+            watermarks: Watermarks::new(),
+            on_failure: None,
+            movable: None,
+            migrate: None,
+        }
+    }
+
+    /// Creates an allocator for frame numbers in `0..frames` that keeps its free sets in `bits`,
+    /// see [`BitmapFrameSets::new`].
+    pub fn with_bitmap(bits: &'a mut [usize], frames: usize) -> Self{
+        Self::with_storage(BitmapFrameSets::new(bits, frames))
+    }
+}
+
+impl <S: FrameSets> BuddyAllocator<S> {
+    /// Creates an empty allocator on top of the given free-set storage.
+    pub fn with_storage(storage: S) -> Self{
+        BuddyAllocator {
+            link_list: storage,
+            allocated: 0,
+            user: 0,
+            sum: 0,
+            watermarks: Watermarks::new(),
+            on_failure: None,
+            movable: None,
+            migrate: None,
+        }
     }
     
     /// 从分配器中分配内存[start,end)
     pub fn alloc_frame(&mut self, start:usize,end:usize){
         assert!(start <= end);
+        self.sum += self.insert_blocks(start, end);
+        self.update_watermarks();
+    }
 
+    /// Inserts `[start, end)` into the free sets as the largest blocks its alignment allows,
+    /// without merging. Returns the number of frames inserted.
+    fn insert_blocks(&mut self, start: usize, end: usize) -> usize{
         let mut total = 0;
         let mut current_start = start;
 
@@ -45,38 +116,84 @@ impl BuddyAllocator {
             let size = min(lowbit,prev_power_of_two(end-current_start));
             total += size;
 
-            self.link_list[size.trailing_zeros() as usize].insert(current_start);
+            self.link_list.insert(size.trailing_zeros() as usize, current_start);
             current_start += size;
         }
-        self.sum += total;
+        total
     }
 
     pub fn insert(&mut self, range: Range<usize>){
         self.alloc_frame(range.start, range.end);
     }
 
+    /// Allocates `count` frames, rounded up to a power of two. The allocation fails if it
+    /// would leave fewer than the `min` watermark free.
     pub fn alloc(&mut self, count: usize) -> Option<usize>{
+        self.alloc_flags(count, AllocFlags::empty())
+    }
+
+    /// Same as [`BuddyAllocator::alloc`], but may dip into the reserve below the `min`
+    /// watermark.
+    pub fn alloc_critical(&mut self, count: usize) -> Option<usize>{
+        self.alloc_flags(count, AllocFlags::RESERVE)
+    }
+
+    /// Allocates `count` frames as modified by `flags`. Only `RESERVE`, `NO_WARN` and
+    /// `MOVABLE` mean something to a single allocator: it cannot touch the frames to zero
+    /// them, and it has no rescue callback or zones.
+    pub fn alloc_flags(&mut self, count: usize, flags: AllocFlags) -> Option<usize>{
+        let result = self.alloc_inner(count, flags.contains(AllocFlags::RESERVE));
+        match result{
+            Some(frame) => {
+                if flags.contains(AllocFlags::MOVABLE){
+                    if let Some(movable) = &mut self.movable{
+                        movable.insert(count.next_power_of_two().trailing_zeros() as usize, frame);
+                    }
+                }
+            }
+            None => {
+                if !flags.contains(AllocFlags::NO_WARN){
+                    if let Some(on_failure) = self.on_failure{
+                        on_failure(count);
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    /// Sets a hook called with the frame count of every failed allocation that is not
+    /// flagged `NO_WARN`.
+    pub fn on_alloc_failure(&mut self, hook: fn(usize)){
+        self.on_failure = Some(hook);
+    }
+
+    fn alloc_inner(&mut self, count: usize, critical: bool) -> Option<usize>{
         let size = count.next_power_of_two();
         let class = size.trailing_zeros() as usize;
+        //保留min水位以下的页帧给关键分配
+        if !self.watermarks.allows(self.free_frames(), size, critical){
+            return None;
+        }
     
-        for i in class..self.link_list.len(){
+        for i in class..FRAME_ORDERS{
             //找到第一个不为空的块
-            if !self.link_list[i].is_empty(){
+            if !self.link_list.is_empty(i){
                 for j in (class+1..i+1).rev(){
-                    if let Some(block_ref) = self.link_list[j].iter().next(){
-                        let block = *block_ref;
-                        self.link_list[j-1].insert(block+(1 << (j-1)));
-                        self.link_list[j-1].insert(block);
-                        self.link_list[j].remove(&block);
+                    if let Some(block) = self.link_list.first(j){
+                        self.link_list.insert(j-1, block+(1 << (j-1)));
+                        self.link_list.insert(j-1, block);
+                        self.link_list.remove(j, block);
                     }else{
                         return None;
                     }
                 }
-                let result = self.link_list[class].iter().next().clone();
-                if let Some(result_ref) = result{
-                    let result = *result_ref;
-                    self.link_list[class].remove(&result);
+                let result = self.link_list.first(class);
+                if let Some(result) = result{
+                    self.link_list.remove(class, result);
                     self.allocated += size;
+                    self.user += count;
+                    self.update_watermarks();
                     return  Some(result);
                 }else{
                     return None;
@@ -86,33 +203,273 @@ impl BuddyAllocator {
         None
     }
 
+    /// Allocates exactly the frames in `range`, splitting the free blocks that contain it.
+    /// Used to reserve frames that are already in use, like the kernel image or ACPI tables.
+    /// Returns false without changing anything if any frame of the range is not free.
+    pub fn alloc_at(&mut self, range: Range<usize>) -> bool{
+        let mut current = range.start;
+        while current < range.end{
+            match self.free_block_at(current){
+                Some((order, block)) => current = block + (1 << order),
+                None => return false,
+            }
+        }
+
+        let mut current = range.start;
+        while current < range.end{
+            let (order, block) = self.free_block_at(current).unwrap();
+            self.link_list.remove(order, block);
+            let block_end = block + (1 << order);
+            //把范围外的部分放回空闲集合
+            if block < range.start{
+                self.insert_blocks(block, range.start);
+            }
+            if block_end > range.end{
+                self.insert_blocks(range.end, block_end);
+            }
+            current = block_end;
+        }
+        self.allocated += range.len();
+        self.user += range.len();
+        self.update_watermarks();
+        true
+    }
+
+    /// Returns the order and first frame of the free block containing `frame`.
+    fn free_block_at(&self, frame: usize) -> Option<(usize, usize)>{
+        (0..FRAME_ORDERS).find_map(|order| {
+            let block = frame & !((1 << order) - 1);
+            self.link_list.contains(order, block).then_some((order, block))
+        })
+    }
+
     pub fn dealloc(&mut self, frame: usize, count: usize){
        let size = count.next_power_of_two();
        let class = size.trailing_zeros() as usize;
-       
+       if let Some(movable) = &mut self.movable{
+           movable.remove(class, frame);
+       }
+       self.free_block(frame, class);
+       self.allocated -= size;
+       self.user -= count;
+       self.update_watermarks();
+    }
+
+    /// Allocates exactly `count` contiguous frames whose first frame is a multiple of `align`.
+    /// The block is rounded up to a power of two as in `alloc`, but the unused tail goes back
+    /// to the free sets right away. Free the frames with [`BuddyAllocator::dealloc_aligned`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `align` is not a power of two.
+    pub fn alloc_aligned(&mut self, count: usize, align: usize) -> Option<usize>{
+        assert!(align.is_power_of_two(), "`align` must be a power of 2");
+        let size = count.next_power_of_two().max(align);
+        let start = self.alloc(size)?;
+        self.free_range(start + count..start + size);
+        self.allocated -= size - count;
+        self.user -= size - count;
+        self.update_watermarks();
+        Some(start)
+    }
+
+    /// Frees `count` frames starting at `frame`, as returned by
+    /// [`BuddyAllocator::alloc_aligned`] with the same `count`.
+    pub fn dealloc_aligned(&mut self, frame: usize, count: usize){
+        self.dealloc_range(frame..frame + count);
+    }
+
+    /// Frees exactly the frames in `range`, e.g. a range reserved with
+    /// [`BuddyAllocator::alloc_at`]. Free buddies are merged as in `dealloc`.
+    pub fn dealloc_range(&mut self, range: Range<usize>){
+        self.allocated -= range.len();
+        self.user -= range.len();
+        self.free_range(range);
+        self.update_watermarks();
+    }
+
+    fn free_range(&mut self, range: Range<usize>){
+        let mut current = range.start;
+        while current < range.end{
+            let lowbit = if current > 0{
+                current & (!current+1)
+            }else{
+                usize::MAX
+            };
+            let size = min(lowbit, prev_power_of_two(range.end-current));
+            self.free_block(current, size.trailing_zeros() as usize);
+            current += size;
+        }
+    }
+
+    fn free_block(&mut self, frame: usize, class: usize){
        //合并链表中空闲的块
        let mut current_ptr = frame;
        let mut current_class  = class;
        
-       while  current_class < self.link_list.len(){
+       while  current_class < FRAME_ORDERS{
            let buddy =  current_ptr ^ (1 << current_class);
-           if self.link_list[current_class].remove(&buddy) == true{
+           if self.link_list.remove(current_class, buddy){
                current_ptr = min(current_ptr,buddy);
                current_class += 1;
            }else{
-               self.link_list[current_class].insert(current_ptr);
+               self.link_list.insert(current_class, current_ptr);
                break;
            }
        }
-       self.allocated -= size;
     }
 
+    /// Turns on compaction. From now on, allocations flagged `MOVABLE` are recorded in
+    /// `movable`, which must be able to hold every frame of the allocator, and
+    /// [`BuddyAllocator::compact`] may move them with `migrate`.
+    pub fn enable_compaction(&mut self, movable: S, migrate: MigrateCallback){
+        self.movable = Some(movable);
+        self.migrate = Some(migrate);
+    }
+
+    /// Moves movable blocks smaller than `order` from the bottom of memory to free frames at
+    /// the top, so that the frames they leave behind merge into blocks of `order` and above.
+    /// Only free blocks below `order` take the moved blocks, so compaction never splits a
+    /// block it is meant to create. Stops when the lowest movable block is above the highest
+    /// free block that could take it. A block whose owner refuses to move is no longer treated
+    /// as movable.
+    ///
+    /// `migrate` runs while the allocator is borrowed, so it must not use the allocator.
+    /// Does nothing if compaction was not enabled.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `order` is not below [`FRAME_ORDERS`].
+    pub fn compact(&mut self, order: usize) -> CompactResult{
+        assert!(order < FRAME_ORDERS, "order {} out of range", order);
+        let mut result = CompactResult::default();
+        let migrate = match self.migrate{
+            Some(migrate) => migrate,
+            None => return result,
+        };
+        let before = self.free_blocks_from(order);
+        loop{
+            let movable = self.movable.as_ref().unwrap();
+            //迁移扫描:地址最低的可移动块
+            let source = (0..order)
+                .filter_map(|class| movable.first(class).map(|frame| (frame, class)))
+                .min();
+            let (frame, class) = match source{
+                Some(source) => source,
+                None => break,
+            };
+            //空闲扫描:地址最高的能放下它、又比`order`小的空闲块
+            let target = (class..order)
+                .filter_map(|order| self.link_list.last(order).map(|block| (block, order)))
+                .max();
+            let (block, block_order) = match target{
+                Some(target) => target,
+                None => break,
+            };
+            let to = block + (1 << block_order) - (1 << class);
+            if to < frame{
+                break;
+            }
+
+            //取空闲块的最高部分,低地址部分放回
+            self.link_list.remove(block_order, block);
+            self.insert_blocks(block, to);
+            let movable = self.movable.as_mut().unwrap();
+            movable.remove(class, frame);
+            if migrate(frame, to, 1 << class){
+                movable.insert(class, to);
+                self.free_block(frame, class);
+                result.migrated += 1;
+            }else{
+                self.free_block(to, class);
+                result.failed += 1;
+            }
+        }
+        let after = self.free_blocks_from(order);
+        debug_assert!(after >= before, "compaction lost free blocks of order {}", order);
+        result.created = after - before;
+        result
+    }
+
+    /// Returns the number of free blocks of `order` the free sets hold, counting higher orders
+    /// in units of `order`.
+    fn free_blocks_from(&self, order: usize) -> usize{
+        (order..FRAME_ORDERS)
+            .map(|class| self.link_list.count(class) << (class - order))
+            .sum()
+    }
+
+    fn free_frames(&self) -> usize{
+        self.sum - self.allocated
+    }
+
+    fn update_watermarks(&mut self){
+        let free = self.free_frames();
+        self.watermarks.update(free);
+    }
+
+    /// Sets the watermarks, in free frames. Frames below `min` are kept for
+    /// [`BuddyAllocator::alloc_critical`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics unless `min <= low <= high`.
+    pub fn set_watermarks(&mut self, min: usize, low: usize, high: usize){
+        let free = self.free_frames();
+        self.watermarks.set(min, low, high, free);
+    }
+
+    /// Registers a callback that fires whenever the free frames cross a watermark.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`MAX_WATERMARK_CALLBACKS`](super::MAX_WATERMARK_CALLBACKS) callbacks
+    /// are already registered.
+    pub fn on_watermark(&mut self, callback: WatermarkCallback){
+        self.watermarks.register(callback);
+    }
+
+    pub fn watermarks(&self) -> &Watermarks{
+        &self.watermarks
+    }
+
+    /// Returns the number of frames handed out.
+    pub fn stats_allocated(&self) -> usize{
+        self.allocated
+    }
+
+    /// Returns the number of frames added with `insert`.
+    pub fn stats_total(&self) -> usize{
+        self.sum
+    }
+
+    /// Returns the free blocks per order and the frames lost to rounding counts up to a power
+    /// of two.
+    pub fn fragmentation(&self) -> FragmentationReport{
+        let mut report = FragmentationReport::new(self.allocated - self.user);
+        for order in 0..FRAME_ORDERS{
+            report.add_free(1 << order, self.link_list.count(order));
+        }
+        report
+    }
+
+    /// Returns the free-set storage.
+    pub fn storage(&self) -> &S{
+        &self.link_list
+    }
 }
 
-#[cfg(feature = "use_spin")]
-pub struct LockedFrameAllocator(Mutex<BuddyAllocator>);
+#[cfg(feature = "alloc")]
+impl Default for BuddyAllocator {
+    fn default() -> Self{
+        Self::new()
+    }
+}
 
 #[cfg(feature = "use_spin")]
+pub struct LockedFrameAllocator<S: FrameSets = DefaultFrameSets>(Mutex<BuddyAllocator<S>>);
+
+#[cfg(all(feature = "use_spin", feature = "alloc"))]
 impl LockedFrameAllocator {
     /// Creates an empty heap
     pub fn new() -> LockedFrameAllocator {
@@ -121,10 +478,26 @@ impl LockedFrameAllocator {
 }
 
 #[cfg(feature = "use_spin")]
-impl Deref for LockedFrameAllocator {
-    type Target = Mutex<BuddyAllocator>;
+impl LockedFrameAllocator<BitmapFrameSets<'static>> {
+    /// Creates an allocator without storage that can live in a `static`. Install the storage
+    /// with `*allocator.lock() = BuddyAllocator::with_bitmap(..)` during boot.
+    pub const fn empty() -> Self {
+        LockedFrameAllocator(Mutex::new(BuddyAllocator::empty()))
+    }
+}
+
+#[cfg(all(feature = "use_spin", feature = "alloc"))]
+impl Default for LockedFrameAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "use_spin")]
+impl <S: FrameSets> Deref for LockedFrameAllocator<S> {
+    type Target = Mutex<BuddyAllocator<S>>;
 
-    fn deref(&self) -> &Mutex<BuddyAllocator> {
+    fn deref(&self) -> &Mutex<BuddyAllocator<S>> {
         &self.0
     }
 }
@@ -0,0 +1,112 @@
+use core::mem::size_of;
+
+/// Maximum number of regions of a `Heap` that can carry a buddy map.
+pub const MAX_MAPPED_REGIONS: usize = 8;
+
+const BITS: usize = usize::BITS as usize;
+
+/// One bit per buddy pair and order for a region of a `Heap`, like the `free_area` maps of old
+/// Linux kernels. The bit of a pair is the XOR of the free state of its two halves: it is
+/// toggled every time one of them enters or leaves the free list. When a block is freed its own
+/// half is not on the list, so the bit alone tells whether the buddy is free.
+pub struct BuddyMap<const ORDER: usize> {
+    start: usize,
+    end: usize,
+    bits: &'static mut [usize],
+    // 每个order的bitmap在`bits`中的起始位置(以word为单位)
+    offsets: [usize; ORDER],
+}
+
+impl<const ORDER: usize> BuddyMap<ORDER> {
+    /// Creates a cleared map for `[start, end)` on top of `bits`.
+    pub(crate) fn new(start: usize, end: usize, bits: &'static mut [usize]) -> Self {
+        let needed = Self::words_needed(start, end);
+        assert!(
+            bits.len() >= needed,
+            "buddy map storage too small: {} words given, {} needed",
+            bits.len(),
+            needed
+        );
+        bits.fill(0);
+
+        let mut offsets = [0; ORDER];
+        let mut offset = 0;
+        for (order, slot) in offsets.iter_mut().enumerate() {
+            *slot = offset;
+            offset += Self::pairs(start, end, order).div_ceil(BITS);
+        }
+        BuddyMap {
+            start,
+            end,
+            bits,
+            offsets,
+        }
+    }
+
+    /// Returns the number of words needed to map `[start, end)`.
+    pub const fn words_needed(start: usize, end: usize) -> usize {
+        let mut words = 0;
+        let mut order = 0;
+        while order < ORDER {
+            words += Self::pairs(start, end, order).div_ceil(BITS);
+            order += 1;
+        }
+        words
+    }
+
+    /// Returns the number of bytes needed to map `[start, end)`.
+    pub const fn bytes_needed(start: usize, end: usize) -> usize {
+        Self::words_needed(start, end) * size_of::<usize>()
+    }
+
+    const fn pairs(start: usize, end: usize, order: usize) -> usize {
+        if order + 1 >= BITS || start >= end {
+            return 0;
+        }
+        let pair = 1 << (order + 1);
+        let low = start & !(pair - 1);
+        let high = (end + pair - 1) & !(pair - 1);
+        (high - low) >> (order + 1)
+    }
+
+    /// Returns the word and bit of the pair containing the block at `addr` of `order`, or `None`
+    /// if the pair is not completely inside the region.
+    fn locate(&self, order: usize, addr: usize) -> Option<(usize, usize)> {
+        if order + 1 >= BITS || order >= ORDER {
+            return None;
+        }
+        let pair = 1 << (order + 1);
+        let pair_start = addr & !(pair - 1);
+        if pair_start < self.start || pair_start + pair > self.end {
+            return None;
+        }
+        let index = (pair_start - (self.start & !(pair - 1))) >> (order + 1);
+        Some((self.offsets[order] + index / BITS, index % BITS))
+    }
+
+    /// Returns true if this map tracks the pair of the block at `addr` of `order`.
+    pub fn covers(&self, order: usize, addr: usize) -> bool {
+        self.locate(order, addr).is_some()
+    }
+
+    /// Flips the pair bit of the block at `addr` of `order`. Returns the new value.
+    pub(crate) fn toggle(&mut self, order: usize, addr: usize) -> bool {
+        let (word, bit) = self.locate(order, addr).expect("block is not covered by this map");
+        self.bits[word] ^= 1 << bit;
+        self.bits[word] & (1 << bit) != 0
+    }
+
+    /// Returns the pair bit of the block at `addr` of `order`.
+    pub fn get(&self, order: usize, addr: usize) -> bool {
+        let (word, bit) = self.locate(order, addr).expect("block is not covered by this map");
+        self.bits[word] & (1 << bit) != 0
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+}
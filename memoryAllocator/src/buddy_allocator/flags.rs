@@ -0,0 +1,51 @@
+use core::ops::{BitOr, BitOrAssign};
+
+/// Modifiers for a single allocation, in the spirit of the GFP flags of Linux. Combine them
+/// with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AllocFlags(u32);
+
+impl AllocFlags {
+    /// Fill the allocation with zeros. Only `Heap` can honour it: `BuddyAllocator` hands out
+    /// frame numbers and has no access to the memory behind them.
+    pub const ZERO: AllocFlags = AllocFlags(1 << 0);
+    /// The caller cannot wait for memory to be reclaimed, so no rescue callback runs.
+    pub const ATOMIC: AllocFlags = AllocFlags(1 << 1);
+    /// The allocation may use the reserve below the `min` watermark.
+    pub const RESERVE: AllocFlags = AllocFlags(1 << 2);
+    /// Only the requested zone is used, whatever the fallback policy says.
+    pub const NO_FALLBACK: AllocFlags = AllocFlags(1 << 3);
+    /// A failure is expected and handled, e.g. an opportunistic high-order allocation with a
+    /// smaller fallback, so the failure hook is not called.
+    pub const NO_WARN: AllocFlags = AllocFlags(1 << 4);
+    /// The frames can be migrated by [`BuddyAllocator::compact`](super::BuddyAllocator::compact).
+    /// Ignored by `Heap`, whose blocks are referenced by plain pointers.
+    pub const MOVABLE: AllocFlags = AllocFlags(1 << 5);
+
+    pub const fn empty() -> Self {
+        AllocFlags(0)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns true if every flag of `other` is set.
+    pub const fn contains(self, other: AllocFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for AllocFlags {
+    type Output = AllocFlags;
+
+    fn bitor(self, other: AllocFlags) -> AllocFlags {
+        AllocFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for AllocFlags {
+    fn bitor_assign(&mut self, other: AllocFlags) {
+        self.0 |= other.0;
+    }
+}
@@ -0,0 +1,210 @@
+#[cfg(feature = "alloc")]
+use alloc::collections::BTreeSet;
+use core::mem::size_of;
+
+/// Number of orders managed by a `BuddyAllocator`.
+pub const FRAME_ORDERS: usize = 32;
+
+/// Storage for the per-order sets of free blocks of a `BuddyAllocator`. A block of order `k`
+/// is identified by its first frame number, which is always a multiple of `2^k`.
+///
+/// `first` must return the lowest free block of the order, so that every storage hands out
+/// frames in the same order.
+pub trait FrameSets {
+    /// Adds the block starting at `frame` to the free set of `order`.
+    fn insert(&mut self, order: usize, frame: usize);
+
+    /// Removes the block starting at `frame` from the free set of `order`. Returns false if it
+    /// was not free.
+    fn remove(&mut self, order: usize, frame: usize) -> bool;
+
+    /// Returns the lowest free block of `order`.
+    fn first(&self, order: usize) -> Option<usize>;
+
+    /// Returns the highest free block of `order`.
+    fn last(&self, order: usize) -> Option<usize>;
+
+    /// Returns the number of free blocks of `order`.
+    fn count(&self, order: usize) -> usize;
+
+    /// Returns true if the block starting at `frame` is in the free set of `order`.
+    fn contains(&self, order: usize, frame: usize) -> bool;
+
+    /// Returns true if `order` has no free block.
+    fn is_empty(&self, order: usize) -> bool {
+        self.first(order).is_none()
+    }
+}
+
+/// The free sets of the frame allocators when no storage is named: [`BTreeFrameSets`] with
+/// the `alloc` feature, [`BitmapFrameSets`] without it.
+#[cfg(feature = "alloc")]
+pub type DefaultFrameSets = BTreeFrameSets;
+/// The free sets of the frame allocators when no storage is named: [`BTreeFrameSets`] with
+/// the `alloc` feature, [`BitmapFrameSets`] without it.
+#[cfg(not(feature = "alloc"))]
+pub type DefaultFrameSets = BitmapFrameSets<'static>;
+
+/// Free sets kept in `BTreeSet`s. Needs a global allocator.
+#[cfg(feature = "alloc")]
+#[derive(Default)]
+pub struct BTreeFrameSets {
+    //最大支持2^32次方
+    sets: [BTreeSet<usize>; FRAME_ORDERS],
+}
+
+#[cfg(feature = "alloc")]
+impl FrameSets for BTreeFrameSets {
+    fn insert(&mut self, order: usize, frame: usize) {
+        self.sets[order].insert(frame);
+    }
+
+    fn remove(&mut self, order: usize, frame: usize) -> bool {
+        self.sets[order].remove(&frame)
+    }
+
+    fn first(&self, order: usize) -> Option<usize> {
+        self.sets[order].iter().next().copied()
+    }
+
+    fn last(&self, order: usize) -> Option<usize> {
+        self.sets[order].iter().next_back().copied()
+    }
+
+    fn count(&self, order: usize) -> usize {
+        self.sets[order].len()
+    }
+
+    fn contains(&self, order: usize, frame: usize) -> bool {
+        self.sets[order].contains(&frame)
+    }
+
+    fn is_empty(&self, order: usize) -> bool {
+        self.sets[order].is_empty()
+    }
+}
+
+const BITS: usize = usize::BITS as usize;
+
+/// Free sets kept as one bitmap per order in caller-supplied storage, so that frames can be
+/// managed before any heap exists. Bit `i` of order `k` is set when the block starting at frame
+/// `i << k` is free.
+pub struct BitmapFrameSets<'a> {
+    bits: &'a mut [usize],
+    frames: usize,
+    // 每个order的bitmap在`bits`中的起始位置(以word为单位)
+    offsets: [usize; FRAME_ORDERS + 1],
+}
+
+impl<'a> BitmapFrameSets<'a> {
+    /// Creates a storage without any capacity.
+    pub const fn empty() -> Self {
+        BitmapFrameSets {
+            bits: &mut [],
+            frames: 0,
+            offsets: [0; FRAME_ORDERS + 1],
+        }
+    }
+
+    /// Creates a storage for frame numbers in `0..frames`. `bits` must hold at least
+    /// [`BitmapFrameSets::words_needed`]`(frames)` words; it is cleared here.
+    pub fn new(bits: &'a mut [usize], frames: usize) -> Self {
+        let offsets = Self::offsets(frames);
+        assert!(
+            bits.len() >= offsets[FRAME_ORDERS],
+            "bitmap storage too small: {} words given, {} needed",
+            bits.len(),
+            offsets[FRAME_ORDERS]
+        );
+        bits.fill(0);
+        BitmapFrameSets {
+            bits,
+            frames,
+            offsets,
+        }
+    }
+
+    /// Returns the number of words needed to track `frames` frames.
+    pub const fn words_needed(frames: usize) -> usize {
+        Self::offsets(frames)[FRAME_ORDERS]
+    }
+
+    /// Returns the number of bytes needed to track `frames` frames.
+    pub const fn bytes_needed(frames: usize) -> usize {
+        Self::words_needed(frames) * size_of::<usize>()
+    }
+
+    /// Returns the number of frames this storage can track.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    const fn offsets(frames: usize) -> [usize; FRAME_ORDERS + 1] {
+        let mut offsets = [0; FRAME_ORDERS + 1];
+        let mut order = 0;
+        while order < FRAME_ORDERS {
+            let blocks = (frames + (1 << order) - 1) >> order;
+            offsets[order + 1] = offsets[order] + blocks.div_ceil(BITS);
+            order += 1;
+        }
+        offsets
+    }
+
+    fn locate(&self, order: usize, frame: usize) -> (usize, usize) {
+        assert!(
+            frame < self.frames,
+            "frame {:#x} is outside the bitmap ({} frames)",
+            frame,
+            self.frames
+        );
+        let bit = frame >> order;
+        (self.offsets[order] + bit / BITS, bit % BITS)
+    }
+}
+
+impl FrameSets for BitmapFrameSets<'_> {
+    fn insert(&mut self, order: usize, frame: usize) {
+        let (word, bit) = self.locate(order, frame);
+        self.bits[word] |= 1 << bit;
+    }
+
+    fn remove(&mut self, order: usize, frame: usize) -> bool {
+        if frame >= self.frames {
+            return false;
+        }
+        let (word, bit) = self.locate(order, frame);
+        let was_free = self.bits[word] & (1 << bit) != 0;
+        self.bits[word] &= !(1 << bit);
+        was_free
+    }
+
+    fn first(&self, order: usize) -> Option<usize> {
+        let words = &self.bits[self.offsets[order]..self.offsets[order + 1]];
+        words
+            .iter()
+            .position(|&word| word != 0)
+            .map(|index| (index * BITS + words[index].trailing_zeros() as usize) << order)
+    }
+
+    fn last(&self, order: usize) -> Option<usize> {
+        let words = &self.bits[self.offsets[order]..self.offsets[order + 1]];
+        words.iter().rposition(|&word| word != 0).map(|index| {
+            (index * BITS + BITS - 1 - words[index].leading_zeros() as usize) << order
+        })
+    }
+
+    fn count(&self, order: usize) -> usize {
+        self.bits[self.offsets[order]..self.offsets[order + 1]]
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    fn contains(&self, order: usize, frame: usize) -> bool {
+        if frame >= self.frames {
+            return false;
+        }
+        let (word, bit) = self.locate(order, frame);
+        self.bits[word] & (1 << bit) != 0
+    }
+}
@@ -29,7 +29,6 @@ impl LinkedList {
     /// Barring the uniqueness constraint, this is equivalent to ensuring that
     /// `*item = some_usize` is a safe operation as long as the pointer resides
     /// in `self`.
-
     pub unsafe fn push(&mut self,item: *mut usize){
         *item = self.head as usize;
         self.head = item;
@@ -52,14 +51,13 @@ impl LinkedList {
     }
 
     /// Returns an iterator over the items in this list.
-    pub fn iter(&self) -> Iter{
+    pub fn iter(&self) -> Iter<'_>{
         Iter { current: self.head, _list: self }
     }
 
     /// The items returned from the iterator (of type `Node`) allows the given
     /// item to be removed from the linked list via the `Node::pop()` method.
-    
-    pub fn iter_mut(&mut self) -> IterMut {
+    pub fn iter_mut(&mut self) -> IterMut<'_> {
         IterMut {
             prev: &mut self.head as *mut *mut usize as *mut usize,
             current: self.head,
@@ -69,6 +67,12 @@ impl LinkedList {
 
 }
 
+impl Default for LinkedList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl fmt::Debug for LinkedList{
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_list().entries(self.iter()).finish()
@@ -80,7 +84,6 @@ pub struct Iter<'a> {
     current: *mut usize
 }
 /// An iterator over the items of the linked list allowing mutability.
-
 pub struct IterMut<'a>{
     _list: &'a mut LinkedList,
     prev: *mut usize,
@@ -128,4 +131,116 @@ impl Node {
     pub fn value(&self) -> *mut usize{
         self.value
     }
-}
\ No newline at end of file
+}
+/// An intrusive doubly linked list. Every item stores the address of the next item in its first
+/// word and the address of the previous one in its second word, so an item whose address is
+/// known can be unlinked in O(1).
+#[derive(Clone, Copy)]
+pub struct DoublyLinkedList{
+    head: *mut usize,
+}
+
+unsafe impl Send for DoublyLinkedList {}
+
+impl DoublyLinkedList {
+
+    pub const fn new() -> DoublyLinkedList {
+        DoublyLinkedList{
+            head: ptr::null_mut()
+        }
+    }
+
+    /// Returns true if the list is empty and false otherwise.
+    pub fn is_empty(&self) -> bool{
+        self.head.is_null()
+    }
+
+    /// Pushes the address `item` to the front of the list.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `item` refers to unique, writeable memory of at least two
+    /// `usize`s that is valid as long as `item` resides in `self`.
+    pub unsafe fn push(&mut self, item: *mut usize){
+        *item = self.head as usize;
+        *item.add(1) = 0;
+        if !self.head.is_null(){
+            *self.head.add(1) = item as usize;
+        }
+        self.head = item;
+    }
+
+    /// Removes and returns the first item in the list, if any.
+    pub fn pop(&mut self) -> Option<*mut usize>{
+        let item = self.peek()?;
+        unsafe{ self.remove(item) };
+        Some(item)
+    }
+
+    /// Returns the first item in the list without removing it, if any.
+    pub fn peek(&self) -> Option<*mut usize>{
+        match self.is_empty(){
+            true => None,
+            false => Some(self.head),
+        }
+    }
+
+    /// Unlinks `item` from the list.
+    ///
+    /// # Safety
+    ///
+    /// `item` must currently be in this list.
+    pub unsafe fn remove(&mut self, item: *mut usize){
+        let next = *item as *mut usize;
+        let prev = *item.add(1) as *mut usize;
+        if prev.is_null(){
+            self.head = next;
+        }else{
+            *prev = next as usize;
+        }
+        if !next.is_null(){
+            *next.add(1) = prev as usize;
+        }
+    }
+
+    /// Returns true if `item` is in the list. This walks the list.
+    pub fn contains(&self, item: *mut usize) -> bool{
+        self.iter().any(|current| current == item)
+    }
+
+    /// Returns an iterator over the items in this list.
+    pub fn iter(&self) -> DoublyIter<'_>{
+        DoublyIter { current: self.head, _list: self }
+    }
+}
+
+impl Default for DoublyLinkedList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for DoublyLinkedList{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// An iterator over the items of a `DoublyLinkedList`.
+pub struct DoublyIter<'a> {
+    _list: &'a DoublyLinkedList,
+    current: *mut usize
+}
+
+impl Iterator for DoublyIter<'_> {
+    type Item = *mut usize;
+
+    fn next(&mut self) -> Option<Self::Item>{
+        if self.current.is_null(){
+            return None;
+        }
+        let item = self.current;
+        self.current = unsafe { *item as *mut usize };
+        Some(item)
+    }
+}
@@ -1,36 +1,77 @@
-#![cfg_attr(feature = "const_fn", feature(const_mut_refs, const_fn_fn_ptr_basics))]
-#![no_std]
-
-#[cfg(test)]
-#[macro_use]
-extern crate std;
-extern crate alloc;
-
 #[cfg(feature = "use_spin")]
-extern crate spin;
-
-use core::alloc::{GlobalAlloc,Layout};
+use core::alloc::GlobalAlloc;
+use core::alloc::Layout;
 use core::cmp::{max,min};
 use core::fmt;
+use core::iter;
 use core::mem::size_of;
 #[cfg(feature = "use_spin")]
 use core::ops::Deref;
+use core::ops::Range;
 use core::ptr::NonNull;
 #[cfg(feature="use_spin")]
 use spin::Mutex;
 
+use crate::error::{AllocError, InvariantError, RemoveError};
+#[cfg(feature = "debug_checks")]
+use crate::error::InvalidFree;
+#[cfg(feature = "debug_checks")]
+use alloc_map::CheckedRegion;
+use crate::fragmentation::FragmentationReport;
+use crate::raw_heap::{HeapStats, RawHeap};
+
 #[cfg(test)]
 mod test;
+#[cfg(feature = "debug_checks")]
+mod alloc_map;
 mod buddy;
+mod buddy_map;
+mod flags;
+mod frame_sets;
+pub mod linked_list;
+#[cfg(feature = "use_spin")]
+mod numa;
+#[cfg(feature = "use_spin")]
+mod pcp;
+mod watermark;
+mod zone;
 
+#[cfg(feature = "debug_checks")]
+pub use alloc_map::{AllocMap, MAX_CHECKED_REGIONS};
 pub use buddy::*;
+pub use buddy_map::*;
+pub use flags::*;
+pub use frame_sets::*;
+#[cfg(feature = "use_spin")]
+pub use numa::*;
+#[cfg(feature = "use_spin")]
+pub use pcp::*;
+pub use watermark::*;
+pub use zone::*;
+
+/// Smallest block managed by `Heap`: a free block holds the `next` and `prev` links of its
+/// free list.
+pub const MIN_BLOCK_SIZE: usize = 2 * size_of::<usize>();
 
 pub struct Heap<const ORDER: usize>{
-    free_list:[linked_list::LinkedList; ORDER],
+    free_list:[linked_list::DoublyLinkedList; ORDER],
+    // 可选的伙伴位图,用于O(1)判断伙伴块是否空闲
+    maps:[Option<BuddyMap<ORDER>>; MAX_MAPPED_REGIONS],
 
     user:usize,
     allocated:usize, //已经分配
     sum :usize,
+    watermarks: Watermarks,
+    // 分配失败时的回调
+    on_failure: Option<fn(&Layout, &AllocError)>,
+    // 加入堆的区域,以及可选的分配位图
+    #[cfg(feature = "debug_checks")]
+    checked: [Option<CheckedRegion>; MAX_CHECKED_REGIONS],
+    // 有区域没能记录下来,无法再判断指针是否在堆内
+    #[cfg(feature = "debug_checks")]
+    untracked: bool,
+    #[cfg(feature = "debug_checks")]
+    on_invalid_free: fn(&InvalidFree),
 }
 
 impl <const ORDER: usize> Heap<ORDER> {
@@ -39,10 +80,19 @@ impl <const ORDER: usize> Heap<ORDER> {
     pub const  fn new()-> Self{
         Heap { 
 
-            free_list:[linked_list::LinkedList::new();ORDER],
+            free_list:[linked_list::DoublyLinkedList::new();ORDER],
+            maps: [const { None }; MAX_MAPPED_REGIONS],
             user: 0, 
             allocated: 0,
             sum: 0,
+            watermarks: Watermarks::new(),
+            on_failure: None,
+            #[cfg(feature = "debug_checks")]
+            checked: [const { None }; MAX_CHECKED_REGIONS],
+            #[cfg(feature = "debug_checks")]
+            untracked: false,
+            #[cfg(feature = "debug_checks")]
+            on_invalid_free: panic_on_invalid_free,
         }
     }
 
@@ -52,106 +102,578 @@ impl <const ORDER: usize> Heap<ORDER> {
     }
 
     //从内存中分配heap,分配的范围在[start,end)
+    ///
+    /// # Safety
+    ///
+    /// The range `[start, end)` must be valid memory that is not used for anything else.
     pub unsafe fn free_heap(&mut self, mut start: usize,mut end:usize){
+        #[cfg(feature = "debug_checks")]
+        self.track_region(start..end);
         //避免在某些平台上访问内存对齐
-        start = (start+size_of::<usize>()-1) & (!size_of::<usize>()+1);
-        end = end & (!size_of::<usize>()+1);
+        start = (start+MIN_BLOCK_SIZE-1) & (!MIN_BLOCK_SIZE+1);
+        end &= !MIN_BLOCK_SIZE+1;
         assert!(start<= end);
+        self.sum += self.push_range(start, end);
+        self.update_watermarks();
+    }
+
+    /// Pushes `[start, end)` onto the free lists as the largest blocks its alignment allows.
+    /// Returns the number of bytes pushed.
+    unsafe fn push_range(&mut self, start: usize, end: usize) -> usize{
         let mut sum = 0;
         let mut current_start = start;
 
-        while current_start+size_of::<usize>() <= end{
+        while current_start+MIN_BLOCK_SIZE <= end{
             let lowbit = current_start & (!current_start+1);
             let size = min(lowbit,prev_power_of_two(end-current_start));
             sum += size;
 
-            self.free_list[size.trailing_zeros() as usize].push(current_start as *mut usize);
+            self.free_block(current_start, size.trailing_zeros() as usize);
             current_start += size;
         }
-        self.sum += sum;
+        sum
+    }
+
+    /// Removes `[start, end)` from the heap if no byte of it is allocated, so that the memory
+    /// can be handed back, e.g. to a hypervisor. Free blocks that stick out of the range are
+    /// split and their outer parts stay in the heap. The heap does not remember its regions,
+    /// so bytes that were never added to it are reported as live.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `start` or `end` is not a multiple of [`MIN_BLOCK_SIZE`].
+    pub fn try_remove_region(&mut self, start: usize, end: usize) -> Result<(), RemoveError>{
+        assert!(
+            start.is_multiple_of(MIN_BLOCK_SIZE) && end.is_multiple_of(MIN_BLOCK_SIZE),
+            "the range must be aligned to MIN_BLOCK_SIZE"
+        );
+        if let Some(first) = self.live_blocks(start, end).next(){
+            let live = self.live_blocks(start, end).map(|block| block.len()).sum();
+            return Err(RemoveError::InUse { live, first });
+        }
+
+        let mut current = start;
+        while current < end{
+            let (class, block) = self
+                .free_block_at(current)
+                .expect("a free range must be covered by free blocks");
+            self.take_free(class, block);
+            let block_end = block + (1 << class);
+            unsafe{
+                //把超出范围的部分放回空闲链表
+                if block < start{
+                    self.push_range(block, start);
+                }
+                if block_end > end{
+                    self.push_range(end, block_end);
+                }
+            }
+            current = block_end;
+        }
+        self.sum -= end - start;
+        self.update_watermarks();
+        #[cfg(feature = "debug_checks")]
+        self.untrack_range(start..end);
+        Ok(())
+    }
+
+    /// Returns the maximal allocated ranges inside `[start, end)`, including memory that
+    /// was never added to the heap.
+    pub fn live_blocks(&self, start: usize, end: usize) -> impl Iterator<Item = Range<usize>> + '_{
+        let mut current = start;
+        iter::from_fn(move || {
+            while current < end{
+                match self.free_block_at(current){
+                    Some((class, block)) => current = block + (1 << class),
+                    None => {
+                        let live = current..self.next_free_block(current, end);
+                        current = live.end;
+                        return Some(live);
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    /// Returns the class and start of the free block containing `addr`.
+    fn free_block_at(&self, addr: usize) -> Option<(usize, usize)>{
+        (0..self.free_list.len()).find_map(|class| {
+            let block = addr & !((1 << class) - 1);
+            // 不能用伙伴位图:它只在伙伴块已分配时才能说明块是否空闲
+            self.free_list[class]
+                .contains(block as *mut usize)
+                .then_some((class, block))
+        })
+    }
+
+    /// Returns the start of the first free block behind `addr`, or `end` if there is none
+    /// before it.
+    fn next_free_block(&self, addr: usize, end: usize) -> usize{
+        self.free_list
+            .iter()
+            .flat_map(|list| list.iter())
+            .map(|block| block as usize)
+            .filter(|&block| block > addr && block < end)
+            .min()
+            .unwrap_or(end)
+    }
+
+    /// Same as [`Heap::free_heap`], but also attaches a [`BuddyMap`] for the region, so that
+    /// `dealloc` can tell whether a buddy is free without walking the free list. `map` must hold
+    /// at least `BuddyMap::<ORDER>::words_needed(start, end)` words.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::free_heap`].
+    pub unsafe fn free_heap_with_map(&mut self, start: usize, end: usize, map: &'static mut [usize]){
+        let slot = self
+            .maps
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .expect("too many regions with a buddy map");
+        *slot = Some(BuddyMap::new(start, end, map));
+        self.free_heap(start, end);
+    }
+
+    fn map_for(&mut self, class: usize, block: usize) -> Option<&mut BuddyMap<ORDER>>{
+        self.maps
+            .iter_mut()
+            .flatten()
+            .find(|map| map.covers(class, block))
+    }
+
+    /// Pushes `block` onto the free list of `class`, keeping the buddy map in sync.
+    unsafe fn push_free(&mut self, class: usize, block: usize){
+        if let Some(map) = self.map_for(class, block){
+            map.toggle(class, block);
+        }
+        self.free_list[class].push(block as *mut usize);
+    }
+
+    /// Frees `block` of `class`, merging it with its free buddies.
+    ///
+    /// # Safety
+    ///
+    /// `block` must be unused heap memory of `2^class` bytes, aligned to its size.
+    unsafe fn free_block(&mut self, block: usize, class: usize){
+        //合并伙伴块
+        let mut current_ptr = block;
+        let mut current_class = class;
+        while current_class + 1 < self.free_list.len(){
+            let buddy = current_ptr ^ (1 << current_class);
+            //Free buddy found
+            if self.take_free(current_class, buddy){
+                current_ptr = min(current_ptr,buddy);
+                current_class += 1;
+            }else{
+                break;
+            }
+        }
+        //回收块到链表中
+        self.push_free(current_class, current_ptr);
+    }
+
+    /// Pops a block from the free list of `class`, keeping the buddy map in sync.
+    fn pop_free(&mut self, class: usize) -> Option<usize>{
+        let block = self.free_list[class].pop()? as usize;
+        if let Some(map) = self.map_for(class, block){
+            map.toggle(class, block);
+        }
+        Some(block)
+    }
+
+    /// Returns true if `block` is on the free list of `class`. Only valid while its buddy is
+    /// not free: with a buddy map the pair bit then tells the answer in O(1).
+    fn is_free(&self, class: usize, block: usize) -> bool{
+        match self.maps.iter().flatten().find(|map| map.covers(class, block)){
+            // 当前块不在链表中,所以位图的值就是伙伴块是否空闲
+            Some(map) => map.get(class, block),
+            None => self.free_list[class].contains(block as *mut usize),
+        }
+    }
+
+    /// Removes `block` from the free list of `class` if it is there. With a buddy map the
+    /// answer is known without looking at the list, and the block is unlinked in O(1).
+    fn take_free(&mut self, class: usize, block: usize) -> bool{
+        if !self.is_free(class, block){
+            return false;
+        }
+        if let Some(map) = self.map_for(class, block){
+            map.toggle(class, block);
+        }
+        unsafe{
+            self.free_list[class].remove(block as *mut usize);
+        }
+        true
     }
 
+    /// Adds `size` bytes starting at `start` to the heap.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::free_heap`].
     pub unsafe fn init(&mut self,start: usize,size: usize){
         self.free_heap(start, start+size);
     }
 
     
-    pub fn alloc(&mut self,layout:Layout) -> Result<NonNull<u8>,()>{
-        let size = max(
-            layout.size().next_power_of_two(),
-            max(layout.align(), size_of::<usize>()),
-        );
+    /// Allocates a block for `layout`. The allocation fails if it would leave less than the
+    /// `min` watermark free.
+    pub fn alloc(&mut self,layout:Layout) -> Result<NonNull<u8>, AllocError>{
+        self.alloc_flags(layout, AllocFlags::empty())
+    }
+
+    /// Same as [`Heap::alloc`], but may dip into the reserve below the `min` watermark.
+    pub fn alloc_critical(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError>{
+        self.alloc_flags(layout, AllocFlags::RESERVE)
+    }
+
+    /// Allocates a block for `layout` as modified by `flags`. `ZERO`, `RESERVE` and `NO_WARN`
+    /// are honoured here; the heap has no rescue callback or zones, so `ATOMIC` and
+    /// `NO_FALLBACK` only matter to the types wrapping it.
+    pub fn alloc_flags(&mut self, layout: Layout, flags: AllocFlags) -> Result<NonNull<u8>, AllocError>{
+        match self.alloc_inner(layout, flags.contains(AllocFlags::RESERVE)){
+            Ok(ptr) => {
+                if flags.contains(AllocFlags::ZERO){
+                    unsafe{
+                        ptr.as_ptr().write_bytes(0, layout.size());
+                    }
+                }
+                Ok(ptr)
+            }
+            Err(err) => {
+                if !flags.contains(AllocFlags::NO_WARN){
+                    if let Some(on_failure) = self.on_failure{
+                        on_failure(&layout, &err);
+                    }
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Sets a hook called with every failed allocation that is not flagged `NO_WARN`, e.g. to
+    /// log it. The hook runs with the heap borrowed and must not allocate from it.
+    pub fn on_alloc_failure(&mut self, hook: fn(&Layout, &AllocError)){
+        self.on_failure = Some(hook);
+    }
+
+    fn alloc_inner(&mut self, layout: Layout, critical: bool) -> Result<NonNull<u8>, AllocError>{
+        let size = block_size(&layout);
 
         let class = size.trailing_zeros() as usize;
+        //保留min水位以下的内存给关键分配
+        if !self.watermarks.allows(self.free_bytes(), size, critical){
+            let err = if class >= ORDER{
+                self.alloc_error(layout, class)
+            }else{
+                AllocError::BelowWatermark { layout, free: self.free_bytes() }
+            };
+            return Err(err);
+        }
         for i in class..self.free_list.len(){
             //找到第一个不为空的块
             if !self.free_list[i].is_empty(){
                 // Split buffers
                 for j in (class+1..i+1).rev(){
-                    if let Some(block) = self.free_list[j].pop(){
+                    if let Some(block) = self.pop_free(j){
                         unsafe{
-                            self.free_list[j-1].push((block as usize + (1 << (j-1))) as *mut usize);
-                            self.free_list[j-1].push(block);
+                            self.push_free(j-1, block + (1 << (j-1)));
+                            self.push_free(j-1, block);
                         }
                     }else{
-                        return Err(());
+                        return Err(self.alloc_error(layout, class));
                     }
                 }
 
                 let res = NonNull::new(
-                    self.free_list[class]
-                    .pop().expect("current block should have free space now") as *mut u8,
+                    self.pop_free(class)
+                    .expect("current block should have free space now") as *mut u8,
                 );
                 if let Some(res) = res{
+                    #[cfg(feature = "debug_checks")]
+                    for map in self.alloc_maps(res.as_ptr() as usize, size){
+                        map.mark(res.as_ptr() as usize, size);
+                    }
                     self.user += layout.size();
                     self.allocated += size;
+                    self.update_watermarks();
                     return Ok(res);
                 }else{
-                    return Err(());
+                    return Err(self.alloc_error(layout, class));
                 }
             }
         }
-        Err(())
+        Err(self.alloc_error(layout, class))
+    }
+
+    /// Returns the size of the largest free block, or 0 if the heap is full.
+    pub fn largest_free(&self) -> usize{
+        match self.free_list.iter().rposition(|list| !list.is_empty()){
+            Some(class) => 1 << class,
+            None => 0,
+        }
+    }
+
+    fn alloc_error(&self, layout: Layout, class: usize) -> AllocError{
+        if self.sum == 0{
+            AllocError::Uninitialized { layout }
+        }else if class >= ORDER{
+            AllocError::TooLarge {
+                layout,
+                max_size: 1 << (ORDER - 1),
+            }
+        }else{
+            AllocError::OutOfMemory {
+                layout,
+                largest_free: self.largest_free(),
+            }
+        }
     }
 
     // 从堆上回收内存
     pub fn dealloc(&mut self, ptr: NonNull<u8>, layout:Layout){
-        let size = max(
-            layout.size().next_power_of_two(),
-            max(layout.align(), size_of::<usize>()),
-        );
+        #[cfg(feature = "debug_checks")]
+        if let Err(err) = self.check_dealloc(ptr.as_ptr() as usize, layout){
+            (self.on_invalid_free)(&err);
+            return;
+        }
+        let size = block_size(&layout);
         let class = size.trailing_zeros() as usize;
-
         unsafe{
-            //回收块到链表中
-            self.free_list[class].push(ptr.as_ptr() as *mut usize);
-            //合并伙伴块
-            let mut current_ptr = ptr.as_ptr() as usize;
-            let mut current_class = class;
-            while current_class < self.free_list.len(){
-                let buddy = current_ptr ^ (1 << current_class);
-                let mut flag = false;
-                for block in self.free_list[current_class].iter_mut(){
-                    if block.value() as usize == buddy{
-                        block.pop();
-                        flag = true;
-                        break;
+            self.free_block(ptr.as_ptr() as usize, class);
+        }
+        self.user -= layout.size();
+        self.allocated -= size;
+        self.update_watermarks();
+    }
+
+    /// Resizes the allocation at `ptr` from `layout` to `new_layout`.
+    ///
+    /// A shrink always happens in place: the block is split and its upper halves are freed. A
+    /// grow happens in place when the block is the lower half of every larger block up to the
+    /// new size and all the upper halves are free. Otherwise the allocation is moved.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`Heap::alloc`] with the same `layout`. On success the
+    /// old pointer must not be used any more.
+    pub unsafe fn realloc(&mut self, ptr: NonNull<u8>, layout: Layout, new_layout: Layout) -> Result<NonNull<u8>, AllocError>{
+        let addr = ptr.as_ptr() as usize;
+        let old_class = block_size(&layout).trailing_zeros() as usize;
+        let new_class = block_size(&new_layout).trailing_zeros() as usize;
+
+        // 块总是按自身大小对齐,所以拆分后仍满足新的对齐要求
+        if new_class <= old_class{
+            //拆分,释放高地址的一半
+            for class in (new_class..old_class).rev(){
+                self.push_free(class, addr + (1 << class));
+            }
+            #[cfg(feature = "debug_checks")]
+            self.track_resize(addr, block_size(&layout), block_size(&new_layout));
+            self.resize_stats(&layout, &new_layout);
+            return Ok(ptr);
+        }
+
+        let in_place = new_class < self.free_list.len()
+            && self.watermarks.allows(self.free_bytes(), (1 << new_class) - (1 << old_class), false)
+            && (old_class..new_class)
+                .all(|class| addr & (1 << class) == 0 && self.is_free(class, addr + (1 << class)));
+        if in_place{
+            //合并高地址的伙伴块
+            for class in old_class..new_class{
+                self.take_free(class, addr + (1 << class));
+            }
+            #[cfg(feature = "debug_checks")]
+            self.track_resize(addr, block_size(&layout), block_size(&new_layout));
+            self.resize_stats(&layout, &new_layout);
+            return Ok(ptr);
+        }
+
+        let new_ptr = self.alloc(new_layout)?;
+        core::ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), min(layout.size(), new_layout.size()));
+        self.dealloc(ptr, layout);
+        Ok(new_ptr)
+    }
+
+    fn resize_stats(&mut self, layout: &Layout, new_layout: &Layout){
+        self.user = self.user - layout.size() + new_layout.size();
+        self.allocated = self.allocated - block_size(layout) + block_size(new_layout);
+        self.update_watermarks();
+    }
+
+    /// Same as [`Heap::free_heap`], but also attaches an [`AllocMap`] for the region, so that
+    /// `dealloc` can catch double frees and wrong layouts in it. `bits` must hold at least
+    /// `AllocMap::words_needed(start, end)` words. Frees in regions added without a map are
+    /// only checked against the region bounds and the alignment of the block. Once
+    /// [`MAX_CHECKED_REGIONS`] regions are tracked, further regions are added without any
+    /// check.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Heap::free_heap`].
+    #[cfg(feature = "debug_checks")]
+    pub unsafe fn free_heap_with_checks(&mut self, start: usize, end: usize, bits: &'static mut [usize]){
+        self.free_heap(start, end);
+        let region = self
+            .checked
+            .iter_mut()
+            .flatten()
+            .find(|region| region.range == (start..end));
+        if let Some(region) = region{
+            region.map = Some(AllocMap::new(start, end, bits));
+        }
+    }
+
+    /// Replaces the handler that `dealloc` reports invalid frees to. The default handler
+    /// panics. If the handler returns, the free is ignored and the heap is left untouched.
+    #[cfg(feature = "debug_checks")]
+    pub fn set_invalid_free_handler(&mut self, handler: fn(&InvalidFree)){
+        self.on_invalid_free = handler;
+    }
+
+    /// Records a region added to the heap. If the table is full, frees are no longer checked
+    /// against the region bounds.
+    #[cfg(feature = "debug_checks")]
+    fn track_region(&mut self, range: Range<usize>){
+        match self.checked.iter_mut().find(|slot| slot.is_none()){
+            Some(slot) => *slot = Some(CheckedRegion { range, map: None }),
+            None => self.untracked = true,
+        }
+    }
+
+    /// Cuts `range` out of the tracked regions after it was removed from the heap. A region
+    /// split in two keeps its allocation map in the lower part, where `alloc_maps` still finds
+    /// it for the upper part.
+    #[cfg(feature = "debug_checks")]
+    fn untrack_range(&mut self, range: Range<usize>){
+        for index in 0..MAX_CHECKED_REGIONS{
+            let Some(region) = &mut self.checked[index] else { continue };
+            let current = region.range.clone();
+            if current.end <= range.start || range.end <= current.start{
+                continue;
+            }
+            let left = current.start..range.start.max(current.start);
+            let right = range.end.min(current.end)..current.end;
+            match (left.is_empty(), right.is_empty()){
+                (true, true) => {
+                    // 位图可能还覆盖着之前拆分出来的其它部分
+                    if let Some(map) = self.checked[index].take().unwrap().map{
+                        let span = map.range();
+                        let rest = self
+                            .checked
+                            .iter_mut()
+                            .flatten()
+                            .find(|region| region.range.start < span.end && span.start < region.range.end);
+                        if let Some(rest) = rest{
+                            rest.map = Some(map);
+                        }
                     }
                 }
-
-                //Free buddy found
-                if flag{
-                    self.free_list[current_class].pop();
-                    current_ptr = min(current_ptr,buddy);
-                    current_class += 1;
-                    self.free_list[current_class].push(current_ptr as *mut usize);
-                }else{
-                    break;
+                (false, true) => region.range = left,
+                (true, false) => region.range = right,
+                (false, false) => {
+                    region.range = left;
+                    self.track_region(right);
                 }
             }
         }
-        self.user -= layout.size();
-        self.allocated -= size;
+    }
+
+    /// Returns the allocation maps that `[addr, addr + size)` reaches into.
+    #[cfg(feature = "debug_checks")]
+    fn alloc_maps(&mut self, addr: usize, size: usize) -> impl Iterator<Item = &mut AllocMap>{
+        self.checked
+            .iter_mut()
+            .flatten()
+            .filter_map(|region| region.map.as_mut())
+            .filter(move |map| map.overlaps(addr, size))
+    }
+
+    /// Moves the allocation at `addr` to its new size in the allocation maps.
+    #[cfg(feature = "debug_checks")]
+    fn track_resize(&mut self, addr: usize, size: usize, new_size: usize){
+        for map in self.alloc_maps(addr, size.max(new_size)){
+            map.resize(addr, size, new_size);
+        }
+    }
+
+    /// Returns true if every byte of `[start, end)` lies in a tracked region.
+    #[cfg(feature = "debug_checks")]
+    fn in_regions(&self, start: usize, end: usize) -> bool{
+        let mut current = start;
+        while current < end{
+            let region = self
+                .checked
+                .iter()
+                .flatten()
+                .find(|region| region.range.contains(&current));
+            match region{
+                Some(region) => current = region.range.end,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Checks a call of `dealloc` and forgets the allocation if it is valid.
+    #[cfg(feature = "debug_checks")]
+    fn check_dealloc(&mut self, ptr: usize, layout: Layout) -> Result<(), InvalidFree>{
+        let size = block_size(&layout);
+        if !self.in_regions(ptr, ptr + 1) && !self.untracked{
+            return Err(InvalidFree::OutsideHeap { ptr, layout });
+        }
+        // 块总是按自身大小对齐,并且不会超出堆的区域;相邻区域的块可能合并
+        if !ptr.is_multiple_of(size) || (!self.in_regions(ptr, ptr + size) && !self.untracked){
+            return Err(InvalidFree::WrongLayout { ptr, layout });
+        }
+        // 块之后的一个粒度可能在相邻区域的位图里
+        for map in self.alloc_maps(ptr, size + MIN_BLOCK_SIZE){
+            if map.contains(ptr) && !map.is_start(ptr){
+                return Err(InvalidFree::DoubleFree { ptr, layout });
+            }
+            if !map.has_size(ptr, size){
+                return Err(InvalidFree::WrongLayout { ptr, layout });
+            }
+        }
+        for map in self.alloc_maps(ptr, size){
+            map.unmark(ptr, size);
+        }
+        Ok(())
+    }
+
+    fn free_bytes(&self) -> usize{
+        self.sum - self.allocated
+    }
+
+    fn update_watermarks(&mut self){
+        let free = self.free_bytes();
+        self.watermarks.update(free);
+    }
+
+    /// Sets the watermarks, in bytes of free memory. Memory below `min` is kept for
+    /// [`Heap::alloc_critical`].
+    ///
+    /// # Panics
+    ///
+    /// This method panics unless `min <= low <= high`.
+    pub fn set_watermarks(&mut self, min: usize, low: usize, high: usize){
+        let free = self.free_bytes();
+        self.watermarks.set(min, low, high, free);
+    }
+
+    /// Registers a callback that fires whenever free memory crosses a watermark.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if [`MAX_WATERMARK_CALLBACKS`] callbacks are already registered.
+    pub fn on_watermark(&mut self, callback: WatermarkCallback){
+        self.watermarks.register(callback);
+    }
+
+    pub fn watermarks(&self) -> &Watermarks{
+        &self.watermarks
     }
 
     pub fn stats_alloc_user(&self) -> usize{
@@ -161,6 +683,75 @@ impl <const ORDER: usize> Heap<ORDER> {
     pub fn stats_alloc_actual(&self) -> usize{
         self.allocated
     }
+
+    /// Walks the free lists and checks that every free block is aligned to its size, that no
+    /// two free buddies were left unmerged and that the free blocks add up to `total -
+    /// allocated`. Returns the first violation found. Looking for buddies makes this quadratic
+    /// in the length of each list, so it is meant for debugging.
+    pub fn check_invariants(&self) -> Result<(), InvariantError>{
+        let mut found = 0;
+        for (class, list) in self.free_list.iter().enumerate(){
+            for block in list.iter(){
+                let block = block as usize;
+                if block & ((1 << class) - 1) != 0{
+                    return Err(InvariantError::MisalignedBlock { block, order: class });
+                }
+                // 最高阶的块不再合并
+                let buddy = block ^ (1 << class);
+                if class + 1 < ORDER && list.contains(buddy as *mut usize){
+                    return Err(InvariantError::UnmergedBuddies {
+                        block: min(block, buddy),
+                        buddy: max(block, buddy),
+                        order: class,
+                    });
+                }
+                found += 1 << class;
+            }
+        }
+        let expected = self.sum - self.allocated;
+        if found != expected{
+            return Err(InvariantError::FreeMismatch { expected, found });
+        }
+        Ok(())
+    }
+
+    /// Returns the free blocks per order and the waste from rounding allocations up to a
+    /// power of two. Walks every free list.
+    pub fn fragmentation(&self) -> FragmentationReport{
+        let mut report = FragmentationReport::new(self.allocated - self.user);
+        for (class, list) in self.free_list.iter().enumerate(){
+            report.add_free(1 << class, list.iter().count());
+        }
+        report
+    }
+}
+
+impl <const ORDER: usize> Default for Heap<ORDER> {
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
+impl <const ORDER: usize> RawHeap for Heap<ORDER> {
+    fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError>{
+        Heap::alloc(self, layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout){
+        Heap::dealloc(self, ptr, layout)
+    }
+
+    unsafe fn add_region(&mut self, start: usize, size: usize){
+        self.init(start, size)
+    }
+
+    fn stats(&self) -> HeapStats{
+        HeapStats {
+            total: self.sum,
+            user: self.user,
+            allocated: self.allocated,
+        }
+    }
 }
 
 impl <const ORDER: usize> fmt::Debug for Heap<ORDER> {
@@ -176,8 +767,8 @@ impl <const ORDER: usize> fmt::Debug for Heap<ORDER> {
 #[cfg(feature = "use_spin")]
 pub struct LockedHeap<const ORDER: usize>(Mutex<Heap<ORDER>>);
 
-#[cfg(feature = "use_sin")]
-impl <const ORDER: usize> LockedHeap {
+#[cfg(feature = "use_spin")]
+impl <const ORDER: usize> LockedHeap<ORDER> {
     pub const fn new() -> Self{
         LockedHeap(Mutex::new(Heap::<ORDER>::new()))
     }
@@ -187,6 +778,13 @@ impl <const ORDER: usize> LockedHeap {
     }
 }
 
+#[cfg(feature = "use_spin")]
+impl <const ORDER: usize> Default for LockedHeap<ORDER> {
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
 #[cfg(feature = "use_spin")]
 impl <const ORDER:usize> Deref for LockedHeap<ORDER> {
     type Target = Mutex<Heap<ORDER>>;
@@ -204,12 +802,21 @@ unsafe impl <const ORDER: usize> GlobalAlloc for LockedHeap<ORDER>{
             .lock()
             .alloc(layout)
             .ok()
-            .map_or(0 as *mut u8, |allocation| allocation.as_ptr())
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout){
         self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
     }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        self.0
+            .lock()
+            .realloc(NonNull::new_unchecked(ptr), layout, new_layout)
+            .ok()
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
 }
 
 
@@ -221,7 +828,6 @@ pub struct LockedHeapWithRescue<const ORDER: usize>{
 
 #[cfg(feature = "use_spin")]
 impl <const ORDER: usize> LockedHeapWithRescue<ORDER> {
-    #[cfg(feature="const_fn")]
     pub const fn new(rescue: fn(&mut Heap<ORDER>, &Layout)) -> Self{
         LockedHeapWithRescue {
             inner: Mutex::new(Heap::<ORDER>::new()),
@@ -229,11 +835,20 @@ impl <const ORDER: usize> LockedHeapWithRescue<ORDER> {
         }
     }
 
-    #[cfg(not(feature = "const_fn"))]
-    pub fn new(rescue: fn(&mut Heap<ORDER>, &Layout))->Self{
-        LockedHeapWithRescue{
-            inner:Mutex::new(Heap::<ORDER>::new()),
-            rescue,
+    /// Allocates as [`Heap::alloc_flags`] does. If the heap is exhausted the rescue callback
+    /// runs and the allocation is retried, unless `flags` contains `ATOMIC`.
+    pub fn alloc_flags(&self, layout: Layout, flags: AllocFlags) -> Result<NonNull<u8>, AllocError>{
+        let mut inner = self.inner.lock();
+        if flags.contains(AllocFlags::ATOMIC){
+            return inner.alloc_flags(layout, flags);
+        }
+        //第一次失败不报告,救援之后再试
+        match inner.alloc_flags(layout, flags | AllocFlags::NO_WARN){
+            Ok(allocation) => Ok(allocation),
+            Err(_) => {
+                (self.rescue)(&mut inner, &layout);
+                inner.alloc_flags(layout, flags)
+            }
         }
     }
 }
@@ -250,17 +865,15 @@ impl <const ORDER: usize> Deref for LockedHeapWithRescue<ORDER>{
 #[cfg(feature="use_spin")]
 unsafe impl <const ORDER:usize> GlobalAlloc for LockedHeapWithRescue<ORDER> { 
     unsafe fn alloc(&self,layout:Layout) -> *mut u8{
-        let mut inner = self.inner.lock();
-        match inner.alloc(layout){
-            Ok(allocation) => allocation.as_ptr(),
-            Err(_) => {
-                (self.rescue)(&mut inner, &layout);
-                inner
-                    .alloc(layout)
-                    .ok()
-                    .map_or(0 as *mut u8, |allocation| allocation.as_ptr())
-            }
-        }
+        self.alloc_flags(layout, AllocFlags::empty())
+            .ok()
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8{
+        self.alloc_flags(layout, AllocFlags::ZERO)
+            .ok()
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout:Layout){
@@ -271,6 +884,19 @@ unsafe impl <const ORDER:usize> GlobalAlloc for LockedHeapWithRescue<ORDER> {
 }
 
 
+#[cfg(feature = "debug_checks")]
+fn panic_on_invalid_free(err: &InvalidFree){
+    panic!("{}", err);
+}
+
+/// Size of the block `Heap` hands out for `layout`.
+fn block_size(layout: &Layout) -> usize{
+    max(
+        layout.size().next_power_of_two(),
+        max(layout.align(), MIN_BLOCK_SIZE),
+    )
+}
+
 pub(crate) fn prev_power_of_two(num: usize) ->usize{
-    1 << (8 * (size_of::<usize>()) - num.leading_zeros() as usize - 1)
+    1 << (usize::BITS as usize - num.leading_zeros() as usize - 1)
 }
\ No newline at end of file
@@ -0,0 +1,167 @@
+use super::frame_sets::{DefaultFrameSets, FrameSets};
+use super::BuddyAllocator;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// Maximum number of ranges that can be inserted into a `NumaFrameAllocator`.
+pub const MAX_NUMA_RANGES: usize = 32;
+
+/// Distance of a node to itself, as in the ACPI SLIT.
+pub const LOCAL_DISTANCE: u8 = 10;
+/// Default distance between two different nodes.
+pub const REMOTE_DISTANCE: u8 = 20;
+
+/// Statistics of one node of a `NumaFrameAllocator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeStats {
+    /// Frames inserted into the node.
+    pub total: usize,
+    /// Frames handed out from the node.
+    pub allocated: usize,
+    /// Allocations served by the node they asked for.
+    pub numa_hit: usize,
+    /// Allocations served by this node although another node was asked for.
+    pub numa_miss: usize,
+    /// Allocations that asked for this node but were served by another one.
+    pub numa_foreign: usize,
+}
+
+#[derive(Default)]
+struct NodeCounters {
+    hit: AtomicUsize,
+    miss: AtomicUsize,
+    foreign: AtomicUsize,
+}
+
+/// A frame allocator with one locked `BuddyAllocator` per NUMA node, so that nodes do not
+/// contend on a single lock. An allocation tries the requested node first, then the other
+/// nodes by increasing distance.
+pub struct NumaFrameAllocator<const NODES: usize, S: FrameSets = DefaultFrameSets> {
+    nodes: [Mutex<BuddyAllocator<S>>; NODES],
+    counters: [NodeCounters; NODES],
+    // 每个节点的回退顺序,按距离从近到远
+    order: [[usize; NODES]; NODES],
+    ranges: [Option<(Range<usize>, usize)>; MAX_NUMA_RANGES],
+}
+
+#[cfg(feature = "alloc")]
+impl<const NODES: usize> NumaFrameAllocator<NODES> {
+    /// Creates an empty allocator with `BTreeSet` storage for every node.
+    pub fn new() -> Self {
+        Self::with_nodes(core::array::from_fn(|_| BuddyAllocator::new()))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<const NODES: usize> Default for NumaFrameAllocator<NODES> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const NODES: usize, S: FrameSets> NumaFrameAllocator<NODES, S> {
+    /// Creates an allocator from one empty `BuddyAllocator` per node. All remote nodes start
+    /// at [`REMOTE_DISTANCE`].
+    pub fn with_nodes(nodes: [BuddyAllocator<S>; NODES]) -> Self {
+        let mut allocator = NumaFrameAllocator {
+            nodes: nodes.map(Mutex::new),
+            counters: core::array::from_fn(|_| NodeCounters::default()),
+            order: [[0; NODES]; NODES],
+            ranges: [const { None }; MAX_NUMA_RANGES],
+        };
+        let mut distances = [[REMOTE_DISTANCE; NODES]; NODES];
+        for (node, row) in distances.iter_mut().enumerate() {
+            row[node] = LOCAL_DISTANCE;
+        }
+        allocator.set_distances(&distances);
+        allocator
+    }
+
+    /// Sets the node distances, e.g. from the ACPI SLIT. `distances[a][b]` is the distance
+    /// from node `a` to node `b`. Nodes at equal distance are tried in index order.
+    pub fn set_distances(&mut self, distances: &[[u8; NODES]; NODES]) {
+        for (node, order) in self.order.iter_mut().enumerate() {
+            *order = core::array::from_fn(|index| index);
+            order.sort_unstable_by_key(|&other| (distances[node][other], other));
+        }
+    }
+
+    /// Returns the nodes an allocation for `node` tries, nearest first.
+    pub fn fallback_order(&self, node: usize) -> &[usize; NODES] {
+        &self.order[node]
+    }
+
+    /// Adds the frames in `range` to `node`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `node` is out of range or if [`MAX_NUMA_RANGES`] ranges have
+    /// already been inserted.
+    pub fn insert(&mut self, node: usize, range: Range<usize>) {
+        let slot = self
+            .ranges
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .expect("too many NUMA ranges");
+        *slot = Some((range.clone(), node));
+        self.nodes[node].get_mut().insert(range);
+    }
+
+    /// Returns the node `frame` was inserted into.
+    pub fn node_of(&self, frame: usize) -> Option<usize> {
+        self.ranges
+            .iter()
+            .flatten()
+            .find(|(range, _)| range.contains(&frame))
+            .map(|&(_, node)| node)
+    }
+
+    /// Allocates `count` frames, preferably from `node`. Returns the node that served the
+    /// allocation and the first frame.
+    pub fn alloc(&self, node: usize, count: usize) -> Option<(usize, usize)> {
+        for &candidate in self.order[node].iter() {
+            if let Some(frame) = self.nodes[candidate].lock().alloc(count) {
+                if candidate == node {
+                    self.counters[node].hit.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    self.counters[candidate].miss.fetch_add(1, Ordering::Relaxed);
+                    self.counters[node].foreign.fetch_add(1, Ordering::Relaxed);
+                }
+                return Some((candidate, frame));
+            }
+        }
+        None
+    }
+
+    /// Frees frames returned by [`NumaFrameAllocator::alloc`] to the node they belong to.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `frame` was never inserted.
+    pub fn dealloc(&self, frame: usize, count: usize) {
+        let node = self.node_of(frame).expect("frame does not belong to any node");
+        self.nodes[node].lock().dealloc(frame, count);
+    }
+
+    /// Returns the locked allocator of `node`.
+    pub fn node(&self, node: usize) -> &Mutex<BuddyAllocator<S>> {
+        &self.nodes[node]
+    }
+
+    /// Returns the statistics of `node`.
+    pub fn node_stats(&self, node: usize) -> NodeStats {
+        let (total, allocated) = {
+            let allocator = self.nodes[node].lock();
+            (allocator.stats_total(), allocator.stats_allocated())
+        };
+        let counters = &self.counters[node];
+        NodeStats {
+            total,
+            allocated,
+            numa_hit: counters.hit.load(Ordering::Relaxed),
+            numa_miss: counters.miss.load(Ordering::Relaxed),
+            numa_foreign: counters.foreign.load(Ordering::Relaxed),
+        }
+    }
+}
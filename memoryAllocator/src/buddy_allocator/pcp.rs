@@ -0,0 +1,152 @@
+use super::frame_sets::{DefaultFrameSets, FrameSets};
+use super::LockedFrameAllocator;
+use spin::Mutex;
+
+/// Maximum number of frames a per-CPU list can hold.
+pub const PCP_CAPACITY: usize = 64;
+
+/// Hot order-0 frames of one CPU.
+struct PageList {
+    frames: [usize; PCP_CAPACITY],
+    count: usize,
+}
+
+impl PageList {
+    const fn new() -> Self {
+        PageList {
+            frames: [0; PCP_CAPACITY],
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, frame: usize) {
+        self.frames[self.count] = frame;
+        self.count += 1;
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        if self.count == 0 {
+            return None;
+        }
+        self.count -= 1;
+        Some(self.frames[self.count])
+    }
+}
+
+/// Per-CPU lists of order-0 frames in front of a shared `LockedFrameAllocator`, like the pcp
+/// lists of Linux. Single frames are served from the list of the calling CPU. An empty list is
+/// refilled with `batch` frames under one acquisition of the shared lock, and a list holding
+/// more than `high` frames drains `batch` of them back. Larger requests go straight to the
+/// shared allocator.
+///
+/// The crate does not know how to find the current CPU, so the caller supplies a `cpu_id` hook
+/// returning a value below `CPUS`. Each list still has its own lock, which is uncontended as
+/// long as the hook is accurate. Frames sitting in the lists count as allocated in the
+/// statistics of the shared allocator.
+pub struct PerCpuFrameAllocator<const CPUS: usize, S: FrameSets = DefaultFrameSets> {
+    global: LockedFrameAllocator<S>,
+    lists: [Mutex<PageList>; CPUS],
+    cpu_id: fn() -> usize,
+    batch: usize,
+    high: usize,
+}
+
+impl<const CPUS: usize, S: FrameSets> PerCpuFrameAllocator<CPUS, S> {
+    /// Puts per-CPU lists in front of `global`, with a batch of 16 and a high mark of 48.
+    pub const fn new(global: LockedFrameAllocator<S>, cpu_id: fn() -> usize) -> Self {
+        PerCpuFrameAllocator {
+            global,
+            lists: [const { Mutex::new(PageList::new()) }; CPUS],
+            cpu_id,
+            batch: 16,
+            high: 48,
+        }
+    }
+
+    /// Changes how many frames move between a list and the shared allocator at once, and how
+    /// many frames a list may keep.
+    ///
+    /// # Panics
+    ///
+    /// This method panics unless `0 < batch <= high <= PCP_CAPACITY`.
+    pub fn set_batch(&mut self, batch: usize, high: usize) {
+        assert!(
+            0 < batch && batch <= high && high <= PCP_CAPACITY,
+            "invalid per-CPU batch {} / high {}",
+            batch,
+            high
+        );
+        self.batch = batch;
+        self.high = high;
+    }
+
+    fn list(&self) -> &Mutex<PageList> {
+        let cpu = (self.cpu_id)();
+        assert!(cpu < CPUS, "cpu id {} out of range ({} CPUs)", cpu, CPUS);
+        &self.lists[cpu]
+    }
+
+    /// Allocates `count` frames. Single frames come from the list of the current CPU.
+    pub fn alloc(&self, count: usize) -> Option<usize> {
+        if count != 1 {
+            return self.global.lock().alloc(count);
+        }
+        let mut list = self.list().lock();
+        if list.count == 0 {
+            let mut global = self.global.lock();
+            for _ in 0..self.batch {
+                match global.alloc(1) {
+                    Some(frame) => list.push(frame),
+                    None => break,
+                }
+            }
+        }
+        list.pop()
+    }
+
+    /// Frees `count` frames starting at `frame`. Single frames go to the list of the current
+    /// CPU.
+    pub fn dealloc(&self, frame: usize, count: usize) {
+        if count != 1 {
+            self.global.lock().dealloc(frame, count);
+            return;
+        }
+        let mut list = self.list().lock();
+        // 先腾出空间再放入,`high`可以等于`PCP_CAPACITY`
+        if list.count >= self.high {
+            let mut global = self.global.lock();
+            for _ in 0..self.batch {
+                let frame = list.pop().unwrap();
+                global.dealloc(frame, 1);
+            }
+        }
+        list.push(frame);
+    }
+
+    /// Returns every frame in the list of `cpu` to the shared allocator, e.g. when the CPU goes
+    /// offline. Returns the number of frames drained.
+    pub fn drain(&self, cpu: usize) -> usize {
+        let mut list = self.lists[cpu].lock();
+        let drained = list.count;
+        let mut global = self.global.lock();
+        while let Some(frame) = list.pop() {
+            global.dealloc(frame, 1);
+        }
+        drained
+    }
+
+    /// Drains the lists of all CPUs.
+    pub fn drain_all(&self) -> usize {
+        (0..CPUS).map(|cpu| self.drain(cpu)).sum()
+    }
+
+    /// Returns the number of frames in the list of `cpu`.
+    pub fn cached(&self, cpu: usize) -> usize {
+        self.lists[cpu].lock().count
+    }
+
+    /// Returns the shared allocator.
+    pub fn global(&self) -> &LockedFrameAllocator<S> {
+        &self.global
+    }
+}
@@ -1,7 +1,12 @@
-use crate::linked_list;
-use crate::BuddyAllocator;
-use crate::Heap;
-use crate::LockedHeapWithRescue;
+use super::linked_list;
+use super::{AllocFlags, BitmapFrameSets, BuddyAllocator, BuddyMap, FrameSets};
+use super::{WatermarkLevel, Zone, ZoneFallback, ZoneLayout, ZonedFrameAllocator};
+use super::Heap;
+use crate::error::{AllocError, InvariantError, RemoveError};
+use crate::raw_heap::RawHeap;
+#[cfg(feature = "use_spin")]
+use super::{LockedFrameAllocator, LockedHeapWithRescue, NodeStats, NumaFrameAllocator, PerCpuFrameAllocator};
+#[cfg(feature = "use_spin")]
 use core::alloc::GlobalAlloc;
 use core::alloc::Layout;
 use core::mem::size_of;
@@ -41,6 +46,51 @@ fn test_linked_list() {
     assert_eq!(list.pop(), None);
 }
 
+#[test]
+fn test_doubly_linked_list() {
+    let mut value1: [usize; 2] = [0; 2];
+    let mut value2: [usize; 2] = [0; 2];
+    let mut value3: [usize; 2] = [0; 2];
+    let p1 = value1.as_mut_ptr();
+    let p2 = value2.as_mut_ptr();
+    let p3 = value3.as_mut_ptr();
+    let mut list = linked_list::DoublyLinkedList::new();
+    unsafe {
+        list.push(p1);
+        list.push(p2);
+        list.push(p3);
+    }
+
+    // Test links
+    assert_eq!(value3, [p2 as usize, 0]);
+    assert_eq!(value2, [p1 as usize, p3 as usize]);
+    assert_eq!(value1, [0, p2 as usize]);
+
+    // Test iter
+    let mut iter = list.iter();
+    assert_eq!(iter.next(), Some(p3));
+    assert_eq!(iter.next(), Some(p2));
+    assert_eq!(iter.next(), Some(p1));
+    assert_eq!(iter.next(), None);
+    assert!(list.contains(p2));
+
+    // Test remove from the middle
+    unsafe { list.remove(p2) };
+    assert!(!list.contains(p2));
+    assert_eq!(value3[0], p1 as usize);
+    assert_eq!(value1[1], p3 as usize);
+
+    // Test remove the head
+    unsafe { list.remove(p3) };
+    assert_eq!(list.peek(), Some(p1));
+    assert_eq!(value1, [0, 0]);
+
+    // Test pop
+    assert_eq!(list.pop(), Some(p1));
+    assert_eq!(list.pop(), None);
+    assert!(list.is_empty());
+}
+
 #[test]
 fn test_empty_heap() {
     let mut heap = Heap::<32>::new();
@@ -74,11 +124,13 @@ fn test_heap_oom() {
     assert!(heap.alloc(Layout::from_size_align(1, 1).unwrap()).is_ok());
 }
 
+#[cfg(feature = "use_spin")]
 #[test]
 fn test_heap_oom_rescue() {
     static mut SPACE: [usize; 100] = [0; 100];
     let heap = LockedHeapWithRescue::new(|heap: &mut Heap<32>, _layout: &Layout| unsafe {
-        heap.free_heap(SPACE.as_ptr() as usize, SPACE.as_ptr().add(100) as usize);
+        let start = core::ptr::addr_of!(SPACE) as usize;
+        heap.free_heap(start, start + 100 * size_of::<usize>());
     });
 
     unsafe {
@@ -86,6 +138,95 @@ fn test_heap_oom_rescue() {
     }
 }
 
+#[cfg(feature = "use_spin")]
+#[test]
+fn test_heap_rescue_atomic() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static RESCUES: AtomicUsize = AtomicUsize::new(0);
+    static mut SPACE: [usize; 100] = [0; 100];
+    let heap = LockedHeapWithRescue::new(|heap: &mut Heap<32>, _layout: &Layout| unsafe {
+        RESCUES.fetch_add(1, Ordering::Relaxed);
+        let start = core::ptr::addr_of!(SPACE) as usize;
+        heap.free_heap(start, start + 100 * size_of::<usize>());
+    });
+
+    // 原子分配不调用救援回调
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    assert!(heap.alloc_flags(layout, AllocFlags::ATOMIC).is_err());
+    assert_eq!(RESCUES.load(Ordering::Relaxed), 0);
+    assert!(heap.alloc_flags(layout, AllocFlags::empty()).is_ok());
+    assert_eq!(RESCUES.load(Ordering::Relaxed), 1);
+    assert!(heap.alloc_flags(layout, AllocFlags::ATOMIC).is_ok());
+}
+
+#[test]
+fn test_heap_alloc_flags() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static FAILURES: AtomicUsize = AtomicUsize::new(0);
+    fn on_failure(_layout: &Layout, err: &AllocError) {
+        assert!(matches!(err, AllocError::BelowWatermark { free: 512, .. }));
+        FAILURES.fetch_add(1, Ordering::Relaxed);
+    }
+
+    const SIZE: usize = 1024;
+    let region = Layout::from_size_align(SIZE, SIZE).unwrap();
+    let start = unsafe { std::alloc::alloc(region) };
+    unsafe {
+        start.write_bytes(0xaa, SIZE);
+    }
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.free_heap(start as usize, start as usize + SIZE);
+    }
+    heap.on_alloc_failure(on_failure);
+
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let a = heap.alloc_flags(layout, AllocFlags::ZERO).unwrap();
+    let bytes = unsafe { core::slice::from_raw_parts(a.as_ptr(), 256) };
+    assert!(bytes.iter().all(|&byte| byte == 0));
+    let b = heap.alloc(layout).unwrap();
+
+    // 剩下的一半是保留区
+    heap.set_watermarks(512, 512, 512);
+    assert!(heap.alloc_flags(layout, AllocFlags::NO_WARN).is_err());
+    assert_eq!(FAILURES.load(Ordering::Relaxed), 0);
+    assert!(heap.alloc(layout).is_err());
+    assert_eq!(FAILURES.load(Ordering::Relaxed), 1);
+    let c = heap.alloc_flags(layout, AllocFlags::RESERVE | AllocFlags::NO_WARN).unwrap();
+
+    heap.dealloc(c, layout);
+    heap.dealloc(b, layout);
+    heap.dealloc(a, layout);
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_frame_allocator_alloc_flags() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static FAILED: AtomicUsize = AtomicUsize::new(0);
+    fn on_failure(count: usize) {
+        FAILED.fetch_add(count, Ordering::Relaxed);
+    }
+
+    let mut frame = BuddyAllocator::new();
+    frame.insert(64..128);
+    frame.set_watermarks(16, 16, 16);
+    frame.on_alloc_failure(on_failure);
+
+    // 高阶分配失败时不报告
+    assert_eq!(frame.alloc_flags(64, AllocFlags::NO_WARN), None);
+    assert_eq!(FAILED.load(Ordering::Relaxed), 0);
+    assert_eq!(frame.alloc(64), None);
+    assert_eq!(FAILED.load(Ordering::Relaxed), 64);
+    let a = frame.alloc(32).unwrap();
+    assert_eq!(frame.alloc_flags(32, AllocFlags::empty()), None);
+    assert_eq!(FAILED.load(Ordering::Relaxed), 96);
+    let b = frame.alloc_flags(32, AllocFlags::RESERVE).unwrap();
+    frame.dealloc(b, 32);
+    frame.dealloc(a, 32);
+    assert_eq!(frame.stats_allocated(), 0);
+}
+
 #[test]
 fn test_heap_alloc_and_free() {
     let mut heap = Heap::<32>::new();
@@ -101,12 +242,14 @@ fn test_heap_alloc_and_free() {
     }
 }
 
+#[cfg(feature = "alloc")]
 #[test]
 fn test_empty_frame_allocator() {
     let mut frame = BuddyAllocator::new();
     assert!(frame.alloc(1).is_none());
 }
 
+#[cfg(feature = "alloc")]
 #[test]
 fn test_frame_allocator_add() {
     let mut frame = BuddyAllocator::new();
@@ -121,6 +264,7 @@ fn test_frame_allocator_add() {
     assert!(frame.alloc(2).is_none());
 }
 
+#[cfg(feature = "alloc")]
 #[test]
 fn test_frame_allocator_alloc_and_free() {
     let mut frame = BuddyAllocator::new();
@@ -133,6 +277,7 @@ fn test_frame_allocator_alloc_and_free() {
     }
 }
 
+#[cfg(feature = "alloc")]
 #[test]
 fn test_frame_allocator_alloc_and_free_complex() {
     let mut frame = BuddyAllocator::new();
@@ -145,3 +290,1029 @@ fn test_frame_allocator_alloc_and_free_complex() {
     let addr2 = frame.alloc(1).unwrap();
     assert_ne!(addr1, addr2);
 }
+
+#[test]
+fn test_heap_alloc_errors() {
+    let mut heap = Heap::<16>::new();
+    let layout = Layout::from_size_align(8, 8).unwrap();
+    assert_eq!(
+        heap.alloc(layout),
+        Err(AllocError::Uninitialized { layout })
+    );
+
+    let space: [usize; 100] = [0; 100];
+    unsafe {
+        heap.free_heap(space.as_ptr() as usize, space.as_ptr().add(100) as usize);
+    }
+    let huge = Layout::from_size_align(1 << 16, 8).unwrap();
+    assert_eq!(
+        heap.alloc(huge),
+        Err(AllocError::TooLarge {
+            layout: huge,
+            max_size: 1 << 15
+        })
+    );
+
+    let big = Layout::from_size_align(100 * size_of::<usize>(), 8).unwrap();
+    let err = heap.alloc(big).unwrap_err();
+    assert!(matches!(err, AllocError::OutOfMemory { .. }));
+    assert_eq!(err.layout(), big);
+    assert_eq!(err.largest_free(), heap.largest_free());
+    assert!(err.largest_free() > 0 && err.largest_free() < big.size());
+}
+
+#[test]
+fn test_bitmap_frame_sets() {
+    let mut bits = [0usize; BitmapFrameSets::words_needed(1024)];
+    let mut sets = BitmapFrameSets::new(&mut bits, 1024);
+    assert!(sets.is_empty(3));
+    sets.insert(3, 64);
+    sets.insert(3, 8);
+    sets.insert(3, 1016);
+    assert_eq!(sets.first(3), Some(8));
+    assert_eq!(sets.last(3), Some(1016));
+    assert_eq!(sets.count(3), 3);
+    assert!(sets.remove(3, 1016));
+    assert!(sets.remove(3, 8));
+    assert!(!sets.remove(3, 8));
+    assert_eq!(sets.first(3), Some(64));
+    assert!(sets.is_empty(0));
+    assert!(!sets.remove(0, 4096));
+}
+
+#[test]
+fn test_bitmap_frame_allocator_add() {
+    let mut bits = [0usize; BitmapFrameSets::words_needed(3)];
+    let mut frame = BuddyAllocator::with_bitmap(&mut bits, 3);
+    assert!(frame.alloc(1).is_none());
+
+    frame.insert(0..3);
+    let num = frame.alloc(1);
+    assert_eq!(num, Some(2));
+    let num = frame.alloc(2);
+    assert_eq!(num, Some(0));
+    assert!(frame.alloc(1).is_none());
+    assert!(frame.alloc(2).is_none());
+}
+
+#[test]
+fn test_bitmap_frame_allocator_alloc_and_free_complex() {
+    static mut BITS: [usize; BitmapFrameSets::words_needed(1024)] =
+        [0; BitmapFrameSets::words_needed(1024)];
+    let bits = unsafe { &mut *core::ptr::addr_of_mut!(BITS) };
+    let mut frame = BuddyAllocator::with_bitmap(bits, 1024);
+    frame.alloc_frame(100, 1024);
+    assert_eq!(frame.stats_total(), 924);
+    for _ in 0..10 {
+        let addr = frame.alloc(1).unwrap();
+        frame.dealloc(addr, 1);
+    }
+    let addr1 = frame.alloc(1).unwrap();
+    let addr2 = frame.alloc(1).unwrap();
+    assert_ne!(addr1, addr2);
+    let big = frame.alloc(256).unwrap();
+    assert_eq!(big % 256, 0);
+    assert_eq!(frame.stats_allocated(), 258);
+
+    frame.dealloc(big, 256);
+    frame.dealloc(addr1, 1);
+    frame.dealloc(addr2, 1);
+    assert_eq!(frame.stats_allocated(), 0);
+    // 所有块都合并回去后,可以重新分配出最大的块
+    assert_eq!(frame.alloc(512), Some(512));
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_bitmap_matches_btree() {
+    let mut bits = [0usize; BitmapFrameSets::words_needed(4096)];
+    let mut bitmap = BuddyAllocator::with_bitmap(&mut bits, 4096);
+    let mut btree = BuddyAllocator::new();
+    bitmap.insert(17..4000);
+    btree.insert(17..4000);
+
+    let mut live = std::vec::Vec::new();
+    for count in [1, 3, 8, 100, 7, 1, 512, 33] {
+        let a = bitmap.alloc(count);
+        assert_eq!(a, btree.alloc(count));
+        live.push((a.unwrap(), count));
+    }
+    for (frame, count) in live.into_iter().rev().step_by(2) {
+        bitmap.dealloc(frame, count);
+        btree.dealloc(frame, count);
+    }
+    assert_eq!(bitmap.alloc(64), btree.alloc(64));
+}
+
+#[test]
+fn test_heap_with_buddy_map() {
+    const SIZE: usize = 32 * 1024;
+    // 两块内存按相同方式对齐,保证伙伴块的划分相同
+    let region = Layout::from_size_align(SIZE, SIZE).unwrap();
+    let start = unsafe { std::alloc::alloc(region) } as usize;
+    let plain = unsafe { std::alloc::alloc(region) } as usize;
+    let end = start + SIZE;
+    let map_words = BuddyMap::<32>::words_needed(start, end);
+    let map = std::boxed::Box::leak(std::vec![0usize; map_words].into_boxed_slice());
+
+    let mut mapped = Heap::<32>::new();
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        mapped.free_heap_with_map(start, end, map);
+        heap.free_heap(plain, plain + SIZE);
+    }
+    let offset = plain as isize - start as isize;
+
+    // 两个堆的分配结果应当完全一致(相对于各自的起始地址)
+    let mut live = std::vec::Vec::new();
+    let mut seed = 7usize;
+    for round in 0..400 {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        if live.is_empty() || !seed.is_multiple_of(3) {
+            let layout = Layout::from_size_align(8 << (seed % 7), 8).unwrap();
+            let a = mapped.alloc(layout);
+            let b = heap.alloc(layout);
+            assert_eq!(a.is_ok(), b.is_ok(), "round {}", round);
+            if let (Ok(a), Ok(b)) = (a, b) {
+                assert_eq!(a.as_ptr() as isize + offset, b.as_ptr() as isize);
+                live.push((a, b, layout));
+            }
+        } else {
+            let (a, b, layout) = live.swap_remove(seed % live.len());
+            mapped.dealloc(a, layout);
+            heap.dealloc(b, layout);
+        }
+    }
+    for (a, b, layout) in live {
+        mapped.dealloc(a, layout);
+        heap.dealloc(b, layout);
+    }
+    assert_eq!(mapped.stats_alloc_actual(), 0);
+    assert_eq!(mapped.largest_free(), heap.largest_free());
+}
+
+#[test]
+fn test_buddy_map_bits() {
+    static mut BITS: [usize; 128] = [0; 128];
+    let bits = unsafe { &mut *core::ptr::addr_of_mut!(BITS) };
+    let mut map = BuddyMap::<8>::new(0x1000, 0x2000, bits);
+    assert!(BuddyMap::<8>::words_needed(0x1000, 0x2000) <= 128);
+    assert!(map.covers(3, 0x1000));
+    assert!(!map.covers(3, 0x2000));
+    assert!(!map.covers(3, 0xff8));
+
+    // 伙伴块共享同一个位
+    assert!(map.toggle(3, 0x1008));
+    assert!(map.get(3, 0x1000));
+    assert!(!map.toggle(3, 0x1000));
+    assert!(!map.get(3, 0x1008));
+}
+
+#[test]
+fn test_heap_realloc() {
+    const SIZE: usize = 4096;
+    let region = Layout::from_size_align(SIZE, SIZE).unwrap();
+    let start = unsafe { std::alloc::alloc(region) } as usize;
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.free_heap(start, start + SIZE);
+    }
+    check_realloc(&mut heap, start, SIZE);
+
+    let start = unsafe { std::alloc::alloc(region) } as usize;
+    let map_words = BuddyMap::<32>::words_needed(start, start + SIZE);
+    let map = std::boxed::Box::leak(std::vec![0usize; map_words].into_boxed_slice());
+    let mut mapped = Heap::<32>::new();
+    unsafe {
+        mapped.free_heap_with_map(start, start + SIZE, map);
+    }
+    check_realloc(&mut mapped, start, SIZE);
+}
+
+fn check_realloc(heap: &mut Heap<32>, start: usize, size: usize) {
+
+    // 低地址的一半,伙伴块空闲,可以原地扩展
+    let small = Layout::from_size_align(16, 8).unwrap();
+    let large = Layout::from_size_align(1024, 8).unwrap();
+    let a = heap.alloc(small).unwrap();
+    assert_eq!(a.as_ptr() as usize, start);
+    unsafe { a.as_ptr().write_bytes(0x5a, 16) };
+    let b = unsafe { heap.realloc(a, small, large) }.unwrap();
+    assert_eq!(a, b);
+    assert_eq!(heap.stats_alloc_user(), 1024);
+    assert_eq!(heap.stats_alloc_actual(), 1024);
+
+    // 原地缩小,释放高地址的部分
+    let medium = Layout::from_size_align(100, 8).unwrap();
+    let c = unsafe { heap.realloc(b, large, medium) }.unwrap();
+    assert_eq!(b, c);
+    assert_eq!(heap.stats_alloc_actual(), 128);
+    let d = heap.alloc(Layout::from_size_align(128, 8).unwrap()).unwrap();
+    assert_eq!(d.as_ptr() as usize, start + 128);
+
+    // 高地址的一半无法原地扩展,只能移动
+    let e = unsafe { heap.realloc(d, Layout::from_size_align(128, 8).unwrap(), large) }.unwrap();
+    assert_ne!(d, e);
+    assert_eq!(e.as_ptr() as usize, start + 1024);
+
+    // 伙伴块被占用时也只能移动
+    let quarter = Layout::from_size_align(256, 8).unwrap();
+    let f = unsafe { heap.realloc(c, medium, quarter) }.unwrap();
+    assert_eq!(f, c);
+    let h = heap.alloc(quarter).unwrap();
+    assert_eq!(h.as_ptr() as usize, start + 256);
+    let g = unsafe { heap.realloc(f, quarter, large) }.unwrap();
+    assert_ne!(f, g);
+    assert!(unsafe { core::slice::from_raw_parts(g.as_ptr(), 16) }.iter().all(|&byte| byte == 0x5a));
+
+    heap.dealloc(e, large);
+    heap.dealloc(g, large);
+    heap.dealloc(h, quarter);
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    assert!(heap.alloc(Layout::from_size_align(size, size).unwrap()).is_ok());
+}
+
+#[test]
+fn test_heap_remove_region() {
+    const SIZE: usize = 4096;
+    let region = Layout::from_size_align(SIZE, SIZE).unwrap();
+    let start = unsafe { std::alloc::alloc(region) } as usize;
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.free_heap(start, start + SIZE);
+    }
+
+    let layout = Layout::from_size_align(128, 8).unwrap();
+    let a = heap.alloc(layout).unwrap();
+    assert_eq!(a.as_ptr() as usize, start);
+    assert_eq!(
+        heap.try_remove_region(start, start + 1024),
+        Err(RemoveError::InUse { live: 128, first: start..start + 128 })
+    );
+    let live: std::vec::Vec<_> = heap.live_blocks(start, start + SIZE).collect();
+    assert_eq!(live.len(), 1);
+    assert_eq!(live[0], start..start + 128);
+
+    // 从空闲的大块中间移除一段,两侧的部分留在堆中
+    assert_eq!(heap.try_remove_region(start + 1024, start + 1536), Ok(()));
+    assert_eq!(RawHeap::stats(&heap).total, SIZE - 512);
+    let live: std::vec::Vec<_> = heap.live_blocks(start, start + SIZE).collect();
+    assert_eq!(live, [start..start + 128, start + 1024..start + 1536]);
+    assert_eq!(heap.largest_free(), 2048);
+
+    heap.dealloc(a, layout);
+    assert!(heap.alloc(Layout::from_size_align(1024, 8).unwrap()).is_ok());
+    assert!(heap.alloc(Layout::from_size_align(2048, 8).unwrap()).is_ok());
+    assert!(heap.alloc(Layout::from_size_align(512, 8).unwrap()).is_ok());
+    assert!(heap.alloc(Layout::from_size_align(16, 8).unwrap()).is_err());
+}
+
+#[test]
+fn test_heap_watermarks() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static CROSSINGS: AtomicUsize = AtomicUsize::new(0);
+    static LEVEL: AtomicUsize = AtomicUsize::new(0);
+    fn watch(old: WatermarkLevel, new: WatermarkLevel, _free: usize) {
+        assert_ne!(old, new);
+        CROSSINGS.fetch_add(1, Ordering::Relaxed);
+        LEVEL.store(new as usize, Ordering::Relaxed);
+    }
+
+    const SIZE: usize = 4096;
+    let region = Layout::from_size_align(SIZE, SIZE).unwrap();
+    let start = unsafe { std::alloc::alloc(region) } as usize;
+    let mut heap = Heap::<32>::new();
+    heap.on_watermark(watch);
+    unsafe {
+        heap.free_heap(start, start + SIZE);
+    }
+    heap.set_watermarks(1024, 2048, 3072);
+    assert_eq!(heap.watermarks().level(), WatermarkLevel::High);
+    assert_eq!(CROSSINGS.load(Ordering::Relaxed), 0);
+
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let a = heap.alloc(layout).unwrap();
+    assert_eq!(heap.watermarks().level(), WatermarkLevel::High);
+    let b = heap.alloc(layout).unwrap();
+    assert_eq!(heap.watermarks().level(), WatermarkLevel::Normal);
+    let c = heap.alloc(layout).unwrap();
+    assert_eq!(heap.watermarks().level(), WatermarkLevel::Low);
+    assert_eq!(CROSSINGS.load(Ordering::Relaxed), 2);
+    assert_eq!(LEVEL.load(Ordering::Relaxed), WatermarkLevel::Low as usize);
+
+    // min以下的保留内存只给关键分配
+    let small = Layout::from_size_align(16, 8).unwrap();
+    assert_eq!(
+        heap.alloc(small),
+        Err(AllocError::BelowWatermark { layout: small, free: 1024 })
+    );
+    let d = heap.alloc_critical(layout).unwrap();
+    assert_eq!(heap.watermarks().level(), WatermarkLevel::Min);
+
+    heap.dealloc(d, layout);
+    heap.dealloc(c, layout);
+    heap.dealloc(b, layout);
+    heap.dealloc(a, layout);
+    assert_eq!(heap.watermarks().level(), WatermarkLevel::High);
+    assert_eq!(CROSSINGS.load(Ordering::Relaxed), 6);
+    assert_eq!(LEVEL.load(Ordering::Relaxed), WatermarkLevel::High as usize);
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_frame_allocator_watermarks() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static LOW: AtomicUsize = AtomicUsize::new(0);
+    fn watch(old: WatermarkLevel, new: WatermarkLevel, _free: usize) {
+        if new <= WatermarkLevel::Low && old > WatermarkLevel::Low {
+            LOW.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let mut frame = BuddyAllocator::new();
+    frame.insert(64..128);
+    frame.set_watermarks(8, 16, 32);
+    frame.on_watermark(watch);
+
+    let a = frame.alloc(32).unwrap();
+    assert_eq!(frame.watermarks().level(), WatermarkLevel::High);
+    let b = frame.alloc(16).unwrap();
+    assert_eq!(frame.watermarks().level(), WatermarkLevel::Normal);
+    let c = frame.alloc(8).unwrap();
+    assert_eq!(frame.watermarks().level(), WatermarkLevel::Low);
+    assert_eq!(LOW.load(Ordering::Relaxed), 1);
+
+    // 剩下的8帧是保留区
+    assert_eq!(frame.alloc(1), None);
+    let d = frame.alloc_critical(4).unwrap();
+    assert_eq!(frame.watermarks().level(), WatermarkLevel::Min);
+
+    frame.dealloc(d, 4);
+    frame.dealloc(c, 8);
+    assert_eq!(frame.watermarks().level(), WatermarkLevel::Normal);
+    frame.dealloc(b, 16);
+    frame.dealloc(a, 32);
+    assert_eq!(frame.watermarks().level(), WatermarkLevel::High);
+    assert_eq!(LOW.load(Ordering::Relaxed), 1);
+}
+
+fn check_compact<S: FrameSets>(frame: &mut BuddyAllocator<S>, movable: S) {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static PINNED: AtomicUsize = AtomicUsize::new(usize::MAX);
+    fn migrate(from: usize, to: usize, count: usize) -> bool {
+        assert_eq!(count, 1);
+        assert!(to > from);
+        from != PINNED.load(Ordering::Relaxed)
+    }
+
+    frame.insert(64..128);
+    frame.enable_compaction(movable, migrate);
+    let frames: std::vec::Vec<_> = (0..64)
+        .map(|_| frame.alloc_flags(1, AllocFlags::MOVABLE).unwrap())
+        .collect();
+    // 释放所有奇数帧,剩下的空闲帧都是0阶
+    for &f in frames.iter().filter(|&&f| f % 2 == 1) {
+        frame.dealloc(f, 1);
+    }
+    assert_eq!(frame.alloc_flags(2, AllocFlags::NO_WARN), None);
+
+    PINNED.store(64, Ordering::Relaxed);
+    let result = frame.compact(4);
+    assert_eq!(result.failed, 1);
+    assert_eq!(result.migrated, 16);
+    // 被钉住的64挡住了[64, 80),[80, 96)合并成一个4阶块
+    assert_eq!(result.created, 1);
+    assert_eq!(frame.storage().count(4), 1);
+    assert_eq!(frame.alloc(16), Some(80));
+
+    // 64不再被视为可移动,再压缩一次不会再问它
+    assert_eq!(frame.compact(4), super::CompactResult::default());
+    assert_eq!(frame.stats_allocated(), 48);
+}
+
+#[test]
+fn test_compact_keeps_high_order_blocks() {
+    fn migrate(_: usize, _: usize, _: usize) -> bool {
+        true
+    }
+    let bits = std::vec![0usize; BitmapFrameSets::words_needed(128)].leak();
+    let movable = std::vec![0usize; BitmapFrameSets::words_needed(128)].leak();
+    let mut frame = BuddyAllocator::with_bitmap(bits, 128);
+    frame.insert(64..96);
+    frame.enable_compaction(BitmapFrameSets::new(movable, 128), migrate);
+    let frames: std::vec::Vec<_> = (0..32)
+        .map(|_| frame.alloc_flags(1, AllocFlags::MOVABLE).unwrap())
+        .collect();
+    // [64, 80)里只剩偶数帧,[80, 96)合并成一个4阶块
+    for &f in frames.iter().filter(|&&f| f >= 80 || f % 2 == 1) {
+        frame.dealloc(f, 1);
+    }
+    assert_eq!(frame.storage().count(4), 1);
+
+    // 上方唯一的空闲空间是4阶块,压缩不能拆开它
+    let result = frame.compact(4);
+    assert_eq!(result.created, 0);
+    assert_eq!(frame.storage().count(4), 1);
+    assert_eq!(frame.alloc(16), Some(80));
+    assert_eq!(frame.stats_allocated(), 8 + 16);
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_frame_allocator_compact() {
+    check_compact(&mut BuddyAllocator::new(), Default::default());
+}
+
+#[test]
+fn test_bitmap_frame_allocator_compact() {
+    let bits = std::vec![0usize; BitmapFrameSets::words_needed(256)].leak();
+    let movable = std::vec![0usize; BitmapFrameSets::words_needed(256)].leak();
+    check_compact(
+        &mut BuddyAllocator::with_bitmap(bits, 256),
+        BitmapFrameSets::new(movable, 256),
+    );
+}
+
+#[test]
+fn test_heap_fragmentation() {
+    const SIZE: usize = 4096;
+    let region = Layout::from_size_align(SIZE, SIZE).unwrap();
+    let start = unsafe { std::alloc::alloc(region) } as usize;
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.free_heap(start, start + SIZE);
+    }
+    let report = heap.fragmentation();
+    assert_eq!(report.free_blocks[12], 1);
+    assert_eq!(report.free_block_count(), 1);
+    assert_eq!(report.fragmentation_index(12), None);
+
+    // 100字节占用一个128字节的块,剩下128..2048各一块
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    let a = heap.alloc(layout).unwrap();
+    let report = heap.fragmentation();
+    assert_eq!(report.internal_waste, 28);
+    assert_eq!(report.free, SIZE - 128);
+    assert_eq!(report.largest_free, 2048);
+    for order in 7..12 {
+        assert_eq!(report.free_blocks[order], 1);
+    }
+    assert_eq!(report.free_block_count(), 5);
+    assert_eq!(report.fragmentation_index(11), None);
+    assert_eq!(report.fragmentation_index(12), Some(1000 - (1000 + 968) / 5));
+
+    heap.dealloc(a, layout);
+    assert_eq!(heap.fragmentation().internal_waste, 0);
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_frame_allocator_fragmentation() {
+    let mut frame = BuddyAllocator::new();
+    frame.insert(64..128);
+    let a = frame.alloc(3).unwrap();
+    let b = frame.alloc_aligned(3, 4).unwrap();
+    let report = frame.fragmentation();
+    assert_eq!(report.internal_waste, 1);
+    assert_eq!(report.free, 64 - 4 - 3);
+    assert_eq!(report.largest_free, 32);
+    assert_eq!(report.free_blocks[0], 1);
+    assert_eq!(report.free_blocks[1], 0);
+    assert_eq!(report.free_blocks[3], 1);
+    assert_eq!(report.free_blocks[4], 1);
+    assert_eq!(report.free_blocks[5], 1);
+    // 57帧空闲,但被分成了4块
+    assert_eq!(report.fragmentation_index(6), Some(1000 - (1000 + 890) / 4));
+
+    frame.dealloc(a, 3);
+    frame.dealloc_aligned(b, 3);
+    let report = frame.fragmentation();
+    assert_eq!(report.internal_waste, 0);
+    assert_eq!(report.free_block_count(), 1);
+    // 内存本身不够,不是碎片造成的
+    assert_eq!(report.fragmentation_index(7), Some(0));
+}
+
+#[test]
+fn test_heap_check_invariants() {
+    const SIZE: usize = 4096;
+    let region = Layout::from_size_align(SIZE, SIZE).unwrap();
+    let start = unsafe { std::alloc::alloc(region) } as usize;
+    let mut heap = Heap::<32>::new();
+    // 分两次加入,相邻的伙伴块也要合并
+    unsafe {
+        heap.free_heap(start, start + SIZE / 2);
+        heap.free_heap(start + SIZE / 2, start + SIZE);
+    }
+    assert_eq!(heap.largest_free(), SIZE);
+    assert_eq!(heap.check_invariants(), Ok(()));
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = heap.alloc(layout).unwrap();
+    let b = heap.alloc(layout).unwrap();
+    assert_eq!(heap.check_invariants(), Ok(()));
+
+    // 绕过合并直接放回链表
+    unsafe {
+        heap.push_free(6, a.as_ptr() as usize);
+        heap.push_free(6, b.as_ptr() as usize);
+    }
+    assert_eq!(
+        heap.check_invariants(),
+        Err(InvariantError::UnmergedBuddies { block: start, buddy: start + 64, order: 6 })
+    );
+    assert!(heap.take_free(6, start));
+    assert_eq!(
+        heap.check_invariants(),
+        Err(InvariantError::FreeMismatch { expected: SIZE - 128, found: SIZE - 64 })
+    );
+    assert!(heap.take_free(6, start + 64));
+    unsafe {
+        heap.free_list[7].push((start + 64) as *mut usize);
+    }
+    assert_eq!(
+        heap.check_invariants(),
+        Err(InvariantError::MisalignedBlock { block: start + 64, order: 7 })
+    );
+    unsafe {
+        heap.free_list[7].remove((start + 64) as *mut usize);
+    }
+
+    heap.dealloc(a, layout);
+    heap.dealloc(b, layout);
+    assert_eq!(heap.check_invariants(), Ok(()));
+    assert_eq!(heap.largest_free(), SIZE);
+}
+
+#[cfg(feature = "debug_checks")]
+fn checked_heap() -> (Heap<32>, usize) {
+    const SIZE: usize = 4096;
+    let region = Layout::from_size_align(SIZE, SIZE).unwrap();
+    let start = unsafe { std::alloc::alloc(region) } as usize;
+    let bits = std::vec![0usize; super::AllocMap::words_needed(start, start + SIZE)].leak();
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.free_heap_with_checks(start, start + SIZE, bits);
+    }
+    (heap, start)
+}
+
+#[cfg(feature = "debug_checks")]
+#[test]
+fn test_heap_invalid_free() {
+    use crate::error::InvalidFree;
+    use core::ptr::NonNull;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    // 记录最近一次错误的种类和地址
+    static KIND: AtomicUsize = AtomicUsize::new(0);
+    static PTR: AtomicUsize = AtomicUsize::new(0);
+    fn record(err: &InvalidFree) {
+        let (kind, ptr) = match *err {
+            InvalidFree::DoubleFree { ptr, .. } => (1, ptr),
+            InvalidFree::WrongLayout { ptr, .. } => (2, ptr),
+            InvalidFree::OutsideHeap { ptr, .. } => (3, ptr),
+        };
+        KIND.store(kind, Ordering::Relaxed);
+        PTR.store(ptr, Ordering::Relaxed);
+    }
+    fn last() -> (usize, usize) {
+        (KIND.swap(0, Ordering::Relaxed), PTR.load(Ordering::Relaxed))
+    }
+
+    let (mut heap, _) = checked_heap();
+    heap.set_invalid_free_handler(record);
+    let small = Layout::from_size_align(64, 8).unwrap();
+    let large = Layout::from_size_align(128, 8).unwrap();
+    let a = heap.alloc(small).unwrap();
+    let b = heap.alloc(large).unwrap();
+    let (a_addr, b_addr) = (a.as_ptr() as usize, b.as_ptr() as usize);
+
+    heap.dealloc(a, small);
+    assert_eq!(last().0, 0);
+    heap.dealloc(a, small);
+    assert_eq!(last(), (1, a_addr));
+
+    // 错误的布局:太小、太大、或者没有按块大小对齐
+    heap.dealloc(b, small);
+    assert_eq!(last(), (2, b_addr));
+    heap.dealloc(b, Layout::from_size_align(512, 8).unwrap());
+    assert_eq!(last(), (2, b_addr));
+    heap.dealloc(NonNull::new((b_addr + 64) as *mut u8).unwrap(), small);
+    assert_eq!(last(), (1, b_addr + 64));
+    heap.dealloc(NonNull::new((b_addr + 16) as *mut u8).unwrap(), small);
+    assert_eq!(last(), (2, b_addr + 16));
+
+    let outside = std::boxed::Box::leak(std::boxed::Box::new([0u64; 8]));
+    heap.dealloc(NonNull::new(outside.as_mut_ptr() as *mut u8).unwrap(), small);
+    assert_eq!(last(), (3, outside.as_ptr() as usize));
+
+    // 无效的释放不会破坏堆
+    assert_eq!(heap.check_invariants(), Ok(()));
+    assert_eq!(heap.stats_alloc_actual(), 128);
+
+    // 原地缩小和扩大之后按新的布局释放
+    let b = unsafe { heap.realloc(b, large, small) }.unwrap();
+    assert_eq!(b.as_ptr() as usize, b_addr);
+    heap.dealloc(b, large);
+    assert_eq!(last(), (2, b_addr));
+    let b = unsafe { heap.realloc(b, small, large) }.unwrap();
+    assert_eq!(b.as_ptr() as usize, b_addr);
+    heap.dealloc(b, large);
+    assert_eq!(last().0, 0);
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    assert_eq!(heap.check_invariants(), Ok(()));
+}
+
+#[cfg(feature = "debug_checks")]
+#[test]
+fn test_heap_checks_many_regions() {
+    use super::MAX_CHECKED_REGIONS;
+    // 区域表满了之后仍然可以加入区域,只是不再检查边界
+    let mut heap = Heap::<32>::new();
+    let mut last = 0;
+    for _ in 0..MAX_CHECKED_REGIONS + 1 {
+        let space = std::boxed::Box::leak(std::boxed::Box::new([0usize; 64]));
+        last = space.as_ptr() as usize;
+        unsafe {
+            heap.free_heap(last, last + size_of_val(space));
+        }
+    }
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    let blocks: std::vec::Vec<_> = core::iter::from_fn(|| heap.alloc(layout).ok()).collect();
+    assert!(blocks.iter().any(|block| (last..last + 512).contains(&(block.as_ptr() as usize))));
+    for block in blocks {
+        heap.dealloc(block, layout);
+    }
+    assert_eq!(heap.stats_alloc_actual(), 0);
+}
+
+#[cfg(feature = "debug_checks")]
+#[test]
+fn test_heap_free_past_region_end() {
+    use crate::error::InvalidFree;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static WRONG: AtomicUsize = AtomicUsize::new(0);
+    fn record(err: &InvalidFree) {
+        if let InvalidFree::WrongLayout { ptr, .. } = *err {
+            WRONG.store(ptr, Ordering::Relaxed);
+        }
+    }
+
+    // 区域的结尾没有按2048对齐,最后一块是1024字节
+    const SIZE: usize = 3072;
+    let start = unsafe { std::alloc::alloc(Layout::from_size_align(4096, 4096).unwrap()) } as usize;
+    let bits = std::vec![0usize; super::AllocMap::words_needed(start, start + SIZE)].leak();
+    let mut heap = Heap::<32>::new();
+    unsafe {
+        heap.free_heap_with_checks(start, start + SIZE, bits);
+    }
+    heap.set_invalid_free_handler(record);
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let blocks: std::vec::Vec<_> = (0..3).map(|_| heap.alloc(layout).unwrap()).collect();
+    let last = *blocks.iter().find(|block| block.as_ptr() as usize == start + 2048).unwrap();
+
+    // 按两倍大小释放最后一块会超出区域
+    heap.dealloc(last, Layout::from_size_align(2048, 8).unwrap());
+    assert_eq!(WRONG.load(Ordering::Relaxed), start + 2048);
+    assert_eq!(heap.stats_alloc_actual(), 3072);
+    for block in blocks {
+        heap.dealloc(block, layout);
+    }
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    assert_eq!(heap.check_invariants(), Ok(()));
+}
+
+#[cfg(feature = "debug_checks")]
+#[test]
+fn test_heap_checks_block_across_regions() {
+    use crate::error::InvalidFree;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static INVALID: AtomicUsize = AtomicUsize::new(0);
+    fn count(_: &InvalidFree) {
+        INVALID.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let start = unsafe { std::alloc::alloc(Layout::from_size_align(4096, 4096).unwrap()) } as usize;
+    let mut heap = Heap::<32>::new();
+    // 两块相邻的区域各有自己的位图,合并出的大块横跨两张位图
+    for half in [start, start + 2048] {
+        let bits = std::vec![0usize; super::AllocMap::words_needed(half, half + 2048)].leak();
+        unsafe {
+            heap.free_heap_with_checks(half, half + 2048, bits);
+        }
+    }
+    heap.set_invalid_free_handler(count);
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+    let block = heap.alloc(layout).unwrap();
+    assert_eq!(block.as_ptr() as usize, start);
+    heap.dealloc(block, Layout::from_size_align(2048, 8).unwrap());
+    assert_eq!(INVALID.load(Ordering::Relaxed), 1);
+    heap.dealloc(block, layout);
+    assert_eq!(INVALID.load(Ordering::Relaxed), 1);
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    assert_eq!(heap.check_invariants(), Ok(()));
+}
+
+#[cfg(feature = "debug_checks")]
+#[test]
+fn test_heap_checks_removed_region() {
+    use crate::error::InvalidFree;
+    use core::ptr::NonNull;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static OUTSIDE: AtomicUsize = AtomicUsize::new(0);
+    fn record(err: &InvalidFree) {
+        if let InvalidFree::OutsideHeap { ptr, .. } = *err {
+            OUTSIDE.store(ptr, Ordering::Relaxed);
+        }
+    }
+
+    let (mut heap, start) = checked_heap();
+    heap.set_invalid_free_handler(record);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = heap.alloc(layout).unwrap();
+    assert_eq!(a.as_ptr() as usize, start);
+
+    // 移除中间的一段之后,释放到这段里的指针在堆外
+    assert_eq!(heap.try_remove_region(start + 1024, start + 2048), Ok(()));
+    let removed = start + 1024;
+    heap.dealloc(NonNull::new(removed as *mut u8).unwrap(), layout);
+    assert_eq!(OUTSIDE.load(Ordering::Relaxed), removed);
+
+    // 两侧剩下的部分仍然受检查
+    OUTSIDE.store(0, Ordering::Relaxed);
+    let upper = heap.alloc(Layout::from_size_align(2048, 8).unwrap()).unwrap();
+    assert_eq!(upper.as_ptr() as usize, start + 2048);
+    heap.dealloc(upper, Layout::from_size_align(2048, 8).unwrap());
+    heap.dealloc(a, layout);
+    assert_eq!(OUTSIDE.load(Ordering::Relaxed), 0);
+    assert_eq!(heap.stats_alloc_actual(), 0);
+    assert_eq!(heap.check_invariants(), Ok(()));
+}
+
+#[cfg(feature = "debug_checks")]
+#[test]
+#[should_panic(expected = "probably a double free")]
+fn test_heap_double_free_panics() {
+    let (mut heap, _) = checked_heap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let a = heap.alloc(layout).unwrap();
+    heap.dealloc(a, layout);
+    heap.dealloc(a, layout);
+}
+
+fn check_alloc_at<S: FrameSets>(frame: &mut BuddyAllocator<S>) {
+    frame.insert(0..1024);
+
+    // 内核镜像占用[100, 300)
+    assert!(frame.alloc_at(100..300));
+    assert_eq!(frame.stats_allocated(), 200);
+    assert!(!frame.alloc_at(299..301));
+    assert!(!frame.alloc_at(50..101));
+    assert_eq!(frame.stats_allocated(), 200);
+
+    // 其余的帧仍然可以分配,但不会落在保留的范围里
+    let mut frames = std::vec::Vec::new();
+    while let Some(f) = frame.alloc(1) {
+        assert!(!(100..300).contains(&f));
+        frames.push(f);
+    }
+    assert_eq!(frames.len(), 1024 - 200);
+    for f in frames {
+        frame.dealloc(f, 1);
+    }
+
+    frame.dealloc_range(100..300);
+    assert_eq!(frame.stats_allocated(), 0);
+    assert!(frame.alloc_at(0..1024));
+    frame.dealloc_range(0..1024);
+    assert_eq!(frame.alloc(512), Some(0));
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_frame_allocator_alloc_at() {
+    check_alloc_at(&mut BuddyAllocator::new());
+}
+
+#[test]
+fn test_bitmap_frame_allocator_alloc_at() {
+    let mut bits = [0usize; BitmapFrameSets::words_needed(1024)];
+    check_alloc_at(&mut BuddyAllocator::with_bitmap(&mut bits, 1024));
+}
+
+fn check_alloc_aligned<S: FrameSets>(frame: &mut BuddyAllocator<S>) {
+    frame.insert(0..2048);
+
+    // 5帧只占用5帧,剩下的3帧可以继续分配
+    let a = frame.alloc_aligned(5, 1).unwrap();
+    assert_eq!(a % 8, 0);
+    assert_eq!(frame.stats_allocated(), 5);
+    assert_eq!(frame.alloc(2), Some(a + 6));
+    assert_eq!(frame.alloc(1), Some(a + 5));
+
+    // 大页: 512帧对齐
+    let huge = frame.alloc_aligned(3, 512).unwrap();
+    assert_eq!(huge % 512, 0);
+    assert_eq!(frame.stats_allocated(), 5 + 3 + 3);
+
+    frame.dealloc(a + 6, 2);
+    frame.dealloc(a + 5, 1);
+    frame.dealloc_aligned(a, 5);
+    frame.dealloc_aligned(huge, 3);
+    assert_eq!(frame.stats_allocated(), 0);
+    assert_eq!(frame.alloc(2048), Some(0));
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_frame_allocator_alloc_aligned() {
+    check_alloc_aligned(&mut BuddyAllocator::new());
+}
+
+#[test]
+fn test_bitmap_frame_allocator_alloc_aligned() {
+    let mut bits = [0usize; BitmapFrameSets::words_needed(2048)];
+    check_alloc_aligned(&mut BuddyAllocator::with_bitmap(&mut bits, 2048));
+}
+
+const TEST_ZONES: ZoneLayout = ZoneLayout {
+    dma_end: 16,
+    dma32_end: 64,
+};
+
+fn check_zones<S: FrameSets>(zoned: &mut ZonedFrameAllocator<S>) {
+    zoned.insert(8..128);
+    assert_eq!(zoned.zone(Zone::Dma).stats_total(), 8);
+    assert_eq!(zoned.zone(Zone::Dma32).stats_total(), 48);
+    assert_eq!(zoned.zone(Zone::Normal).stats_total(), 64);
+
+    let normal = zoned.alloc(64, Zone::Normal).unwrap();
+    assert_eq!(normal, 64);
+    let dma = zoned.alloc(4, Zone::Dma).unwrap();
+    assert!((8..16).contains(&dma));
+    assert_eq!(zoned.zone(Zone::Dma).stats_allocated(), 4);
+
+    // Normal用完之后,回退到DMA32,但保留DMA
+    zoned.set_fallback(ZoneFallback::Strict);
+    assert_eq!(zoned.alloc(1, Zone::Normal), None);
+    zoned.set_fallback(ZoneFallback::LowerExceptDma);
+    let mut frames = std::vec::Vec::new();
+    while let Some(frame) = zoned.alloc(16, Zone::Normal) {
+        assert_eq!(TEST_ZONES.zone_of(frame), Zone::Dma32);
+        frames.push(frame);
+    }
+    assert_eq!(frames.len(), 3);
+    zoned.set_fallback(ZoneFallback::Lower);
+    assert_eq!(zoned.alloc_flags(4, Zone::Normal, AllocFlags::NO_FALLBACK), None);
+    let last = zoned.alloc(4, Zone::Normal).unwrap();
+    assert_eq!(TEST_ZONES.zone_of(last), Zone::Dma);
+    assert_eq!(zoned.stats_allocated(), 64 + 4 + 48 + 4);
+
+    zoned.dealloc(last, 4);
+    for frame in frames {
+        zoned.dealloc(frame, 16);
+    }
+    zoned.dealloc(dma, 4);
+    zoned.dealloc(normal, 64);
+    assert_eq!(zoned.stats_allocated(), 0);
+    assert_eq!(zoned.stats_total(), 120);
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn test_zoned_frame_allocator() {
+    check_zones(&mut ZonedFrameAllocator::new(TEST_ZONES, ZoneFallback::Lower));
+    assert_eq!(ZoneLayout::for_page_size(4096).dma_end, 4096);
+}
+
+#[test]
+fn test_bitmap_zoned_frame_allocator() {
+    let mut dma = [0usize; BitmapFrameSets::words_needed(16)];
+    let mut dma32 = [0usize; BitmapFrameSets::words_needed(64)];
+    let mut normal = [0usize; BitmapFrameSets::words_needed(128)];
+    let zones = [
+        BuddyAllocator::with_bitmap(&mut dma, 16),
+        BuddyAllocator::with_bitmap(&mut dma32, 64),
+        BuddyAllocator::with_bitmap(&mut normal, 128),
+    ];
+    check_zones(&mut ZonedFrameAllocator::with_zones(zones, TEST_ZONES, ZoneFallback::Lower));
+}
+
+#[cfg(feature = "use_spin")]
+#[test]
+fn test_numa_frame_allocator() {
+    let mut bits = [[0usize; BitmapFrameSets::words_needed(256)]; 3];
+    let [a, b, c] = &mut bits;
+    let mut numa = NumaFrameAllocator::<3, _>::with_nodes([
+        BuddyAllocator::with_bitmap(a, 256),
+        BuddyAllocator::with_bitmap(b, 256),
+        BuddyAllocator::with_bitmap(c, 256),
+    ]);
+    numa.insert(0, 64..128);
+    numa.insert(1, 128..192);
+    numa.insert(2, 192..256);
+    assert_eq!(numa.fallback_order(1), &[1, 0, 2]);
+
+    // 节点0离节点2比节点1近
+    numa.set_distances(&[[10, 30, 15], [30, 10, 20], [15, 20, 10]]);
+    assert_eq!(numa.fallback_order(0), &[0, 2, 1]);
+    assert_eq!(numa.fallback_order(1), &[1, 2, 0]);
+
+    let (node, local) = numa.alloc(0, 64).unwrap();
+    assert_eq!((node, local), (0, 64));
+    let (node, remote) = numa.alloc(0, 16).unwrap();
+    assert_eq!(node, 2);
+    assert!((192..256).contains(&remote));
+    assert_eq!(numa.node_of(remote), Some(2));
+
+    assert_eq!(
+        numa.node_stats(0),
+        NodeStats { total: 64, allocated: 64, numa_hit: 1, numa_miss: 0, numa_foreign: 1 }
+    );
+    assert_eq!(
+        numa.node_stats(2),
+        NodeStats { total: 64, allocated: 16, numa_hit: 0, numa_miss: 1, numa_foreign: 0 }
+    );
+
+    numa.dealloc(remote, 16);
+    numa.dealloc(local, 64);
+    assert_eq!(numa.node_stats(0).allocated, 0);
+    assert_eq!(numa.node_stats(2).allocated, 0);
+    assert!(numa.alloc(1, 256).is_none());
+}
+
+#[cfg(feature = "use_spin")]
+#[test]
+fn test_per_cpu_frame_allocator() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static CPU: AtomicUsize = AtomicUsize::new(0);
+    fn current_cpu() -> usize {
+        CPU.load(Ordering::Relaxed)
+    }
+
+    let global = LockedFrameAllocator::empty();
+    let bits = std::boxed::Box::leak(std::vec![0usize; BitmapFrameSets::words_needed(256)].into_boxed_slice());
+    *global.lock() = BuddyAllocator::with_bitmap(bits, 256);
+    global.lock().insert(128..256);
+    let mut pcp = PerCpuFrameAllocator::<2, _>::new(global, current_cpu);
+    pcp.set_batch(4, 8);
+
+    // 第一次分配从全局分配器批量取4帧
+    let a = pcp.alloc(1).unwrap();
+    assert_eq!(pcp.cached(0), 3);
+    assert_eq!(pcp.global().lock().stats_allocated(), 4);
+
+    // 另一个CPU有自己的列表
+    CPU.store(1, Ordering::Relaxed);
+    let b = pcp.alloc(1).unwrap();
+    assert_ne!(a, b);
+    assert_eq!(pcp.cached(1), 3);
+
+    // 大块直接走全局分配器
+    let big = pcp.alloc(64).unwrap();
+    assert_eq!(pcp.cached(1), 3);
+    pcp.dealloc(big, 64);
+
+    // 超过high之后归还一批
+    let frames: std::vec::Vec<_> = (0..6).map(|_| pcp.alloc(1).unwrap()).collect();
+    assert_eq!(pcp.cached(1), 1);
+    pcp.dealloc(b, 1);
+    for frame in frames {
+        pcp.dealloc(frame, 1);
+    }
+    assert_eq!(pcp.cached(1), 8);
+    pcp.dealloc(a, 1);
+    assert_eq!(pcp.cached(1), 5);
+
+    assert_eq!(pcp.drain_all(), 3 + 5);
+    assert_eq!(pcp.global().lock().stats_allocated(), 0);
+    assert_eq!(pcp.global().lock().alloc(128), Some(128));
+}
+
+#[cfg(feature = "use_spin")]
+#[test]
+fn test_per_cpu_frame_allocator_full_list() {
+    use super::PCP_CAPACITY;
+    fn current_cpu() -> usize {
+        0
+    }
+
+    let global = LockedFrameAllocator::empty();
+    let bits = std::boxed::Box::leak(std::vec![0usize; BitmapFrameSets::words_needed(256)].into_boxed_slice());
+    *global.lock() = BuddyAllocator::with_bitmap(bits, 256);
+    global.lock().insert(128..256);
+    let mut pcp = PerCpuFrameAllocator::<1, _>::new(global, current_cpu);
+    pcp.set_batch(16, PCP_CAPACITY);
+
+    // high等于容量时,列表满了也不能越界
+    let frames: std::vec::Vec<_> = (0..PCP_CAPACITY + 1).map(|_| pcp.global().lock().alloc(1).unwrap()).collect();
+    for &frame in &frames[..PCP_CAPACITY] {
+        pcp.dealloc(frame, 1);
+    }
+    assert_eq!(pcp.cached(0), PCP_CAPACITY);
+    pcp.dealloc(frames[PCP_CAPACITY], 1);
+    assert_eq!(pcp.cached(0), PCP_CAPACITY - 16 + 1);
+
+    pcp.drain_all();
+    assert_eq!(pcp.global().lock().stats_allocated(), 0);
+}
@@ -0,0 +1,118 @@
+/// Maximum number of callbacks that can be registered on one allocator.
+pub const MAX_WATERMARK_CALLBACKS: usize = 4;
+
+/// Where the free memory of an allocator stands relative to its watermarks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WatermarkLevel {
+    /// Below `min`: only critical allocations may proceed.
+    Min,
+    /// Below `low`: time to start reclaiming.
+    Low,
+    /// Between `low` and `high`.
+    Normal,
+    /// At or above `high`: reclaim can stop.
+    High,
+}
+
+/// Called with the old level, the new level and the free amount whenever the level of an
+/// allocator changes. It runs while the allocator is borrowed (and locked, for the `Locked*`
+/// types), so it must not allocate from the same allocator.
+pub type WatermarkCallback = fn(WatermarkLevel, WatermarkLevel, usize);
+
+/// The min/low/high watermarks of an allocator and the callbacks watching them. The unit is
+/// the unit of the allocator: bytes for `Heap`, frames for `BuddyAllocator`. All marks start at
+/// 0, so nothing is reserved and no callback fires until they are set.
+pub struct Watermarks {
+    min: usize,
+    low: usize,
+    high: usize,
+    level: WatermarkLevel,
+    callbacks: [Option<WatermarkCallback>; MAX_WATERMARK_CALLBACKS],
+}
+
+impl Watermarks {
+    pub const fn new() -> Self {
+        Watermarks {
+            min: 0,
+            low: 0,
+            high: 0,
+            level: WatermarkLevel::High,
+            callbacks: [None; MAX_WATERMARK_CALLBACKS],
+        }
+    }
+
+    pub fn min(&self) -> usize {
+        self.min
+    }
+
+    pub fn low(&self) -> usize {
+        self.low
+    }
+
+    pub fn high(&self) -> usize {
+        self.high
+    }
+
+    /// Returns the level at the last change of the free amount.
+    pub fn level(&self) -> WatermarkLevel {
+        self.level
+    }
+
+    /// Returns the level for `free`.
+    pub fn level_of(&self, free: usize) -> WatermarkLevel {
+        if free < self.min {
+            WatermarkLevel::Min
+        } else if free < self.low {
+            WatermarkLevel::Low
+        } else if free < self.high {
+            WatermarkLevel::Normal
+        } else {
+            WatermarkLevel::High
+        }
+    }
+
+    pub(crate) fn set(&mut self, min: usize, low: usize, high: usize, free: usize) {
+        assert!(
+            min <= low && low <= high,
+            "watermarks must satisfy min <= low <= high"
+        );
+        self.min = min;
+        self.low = low;
+        self.high = high;
+        self.update(free);
+    }
+
+    pub(crate) fn register(&mut self, callback: WatermarkCallback) {
+        let slot = self
+            .callbacks
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .expect("too many watermark callbacks");
+        *slot = Some(callback);
+    }
+
+    /// Returns true if taking `size` out of `free` keeps the reserve below `min` intact, or if
+    /// the allocation is critical.
+    pub(crate) fn allows(&self, free: usize, size: usize, critical: bool) -> bool {
+        critical || free.saturating_sub(size) >= self.min
+    }
+
+    /// Recomputes the level and fires the callbacks if it changed.
+    pub(crate) fn update(&mut self, free: usize) {
+        let level = self.level_of(free);
+        if level == self.level {
+            return;
+        }
+        let old = self.level;
+        self.level = level;
+        for callback in self.callbacks.iter().flatten() {
+            callback(old, level, free);
+        }
+    }
+}
+
+impl Default for Watermarks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
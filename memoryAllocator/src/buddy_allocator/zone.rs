@@ -0,0 +1,218 @@
+use super::flags::AllocFlags;
+use super::frame_sets::{DefaultFrameSets, FrameSets};
+use super::BuddyAllocator;
+use core::ops::Range;
+
+/// Number of zones of a `ZonedFrameAllocator`.
+pub const ZONE_COUNT: usize = 3;
+
+/// A physical memory zone. Zones are ordered from low to high addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Zone {
+    /// Frames below 16 MiB, for devices with 24-bit DMA.
+    Dma,
+    /// Frames below 4 GiB, for devices with 32-bit DMA.
+    Dma32,
+    /// All other frames.
+    Normal,
+}
+
+impl Zone {
+    /// All zones, from low to high.
+    pub const ALL: [Zone; ZONE_COUNT] = [Zone::Dma, Zone::Dma32, Zone::Normal];
+
+    /// Returns the position of the zone in [`Zone::ALL`].
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Where the zones end, in frame numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZoneLayout {
+    pub dma_end: usize,
+    pub dma32_end: usize,
+}
+
+impl ZoneLayout {
+    /// Returns the usual 16 MiB / 4 GiB limits for frames of `page_size` bytes.
+    pub const fn for_page_size(page_size: usize) -> Self {
+        ZoneLayout {
+            dma_end: (16 << 20) / page_size,
+            dma32_end: ((4u64 << 30) / page_size as u64) as usize,
+        }
+    }
+
+    /// Returns the zone `frame` belongs to.
+    pub fn zone_of(&self, frame: usize) -> Zone {
+        if frame < self.dma_end {
+            Zone::Dma
+        } else if frame < self.dma32_end {
+            Zone::Dma32
+        } else {
+            Zone::Normal
+        }
+    }
+
+    /// Returns the frames covered by `zone`.
+    pub fn range(&self, zone: Zone) -> Range<usize> {
+        match zone {
+            Zone::Dma => 0..self.dma_end,
+            Zone::Dma32 => self.dma_end..self.dma32_end,
+            Zone::Normal => self.dma32_end..usize::MAX,
+        }
+    }
+}
+
+/// What a `ZonedFrameAllocator` does when the requested zone is exhausted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZoneFallback {
+    /// Only the requested zone is used.
+    Strict,
+    /// Every lower zone is tried, highest first.
+    Lower,
+    /// Lower zones are tried, but `Dma` is kept for requests that ask for it.
+    LowerExceptDma,
+}
+
+/// A frame allocator with one `BuddyAllocator` per zone. Inserted ranges are split at the zone
+/// limits. An allocation names the highest zone it can use, and falls back to lower zones as
+/// the [`ZoneFallback`] policy allows.
+pub struct ZonedFrameAllocator<S: FrameSets = DefaultFrameSets> {
+    zones: [BuddyAllocator<S>; ZONE_COUNT],
+    layout: ZoneLayout,
+    fallback: ZoneFallback,
+}
+
+#[cfg(feature = "alloc")]
+impl ZonedFrameAllocator {
+    /// Creates an empty allocator with `BTreeSet` storage for every zone.
+    pub fn new(layout: ZoneLayout, fallback: ZoneFallback) -> Self {
+        Self::with_zones(
+            [BuddyAllocator::new(), BuddyAllocator::new(), BuddyAllocator::new()],
+            layout,
+            fallback,
+        )
+    }
+}
+
+impl<S: FrameSets> ZonedFrameAllocator<S> {
+    /// Creates an allocator from one empty `BuddyAllocator` per zone, in the order of
+    /// [`Zone::ALL`].
+    pub fn with_zones(
+        zones: [BuddyAllocator<S>; ZONE_COUNT],
+        layout: ZoneLayout,
+        fallback: ZoneFallback,
+    ) -> Self {
+        ZonedFrameAllocator {
+            zones,
+            layout,
+            fallback,
+        }
+    }
+
+    /// Adds the frames in `range`, splitting it at the zone limits.
+    pub fn insert(&mut self, range: Range<usize>) {
+        for zone in Zone::ALL {
+            let limits = self.layout.range(zone);
+            let start = range.start.max(limits.start);
+            let end = range.end.min(limits.end);
+            if start < end {
+                self.zones[zone.index()].insert(start..end);
+            }
+        }
+    }
+
+    /// Allocates `count` frames from `zone` or, if it is exhausted, from a lower zone as the
+    /// fallback policy allows.
+    pub fn alloc(&mut self, count: usize, zone: Zone) -> Option<usize> {
+        self.candidates(zone)
+            .find_map(|zone| self.zones[zone.index()].alloc(count))
+    }
+
+    /// Same as [`ZonedFrameAllocator::alloc`], with the exact count and alignment of
+    /// [`BuddyAllocator::alloc_aligned`].
+    pub fn alloc_aligned(&mut self, count: usize, align: usize, zone: Zone) -> Option<usize> {
+        self.candidates(zone)
+            .find_map(|zone| self.zones[zone.index()].alloc_aligned(count, align))
+    }
+
+    /// Same as [`ZonedFrameAllocator::alloc`], as modified by `flags`. `NO_FALLBACK` limits the
+    /// allocation to `zone`, and the other flags are passed on to the zone allocators. Only
+    /// the last zone tried reports a failure.
+    pub fn alloc_flags(&mut self, count: usize, zone: Zone, flags: AllocFlags) -> Option<usize> {
+        let fallback = if flags.contains(AllocFlags::NO_FALLBACK) {
+            ZoneFallback::Strict
+        } else {
+            self.fallback
+        };
+        let mut candidates = Self::candidates_with(zone, fallback).peekable();
+        while let Some(candidate) = candidates.next() {
+            let flags = if candidates.peek().is_some() {
+                flags | AllocFlags::NO_WARN
+            } else {
+                flags
+            };
+            if let Some(frame) = self.zones[candidate.index()].alloc_flags(count, flags) {
+                return Some(frame);
+            }
+        }
+        None
+    }
+
+    fn candidates(&self, zone: Zone) -> impl Iterator<Item = Zone> {
+        Self::candidates_with(zone, self.fallback)
+    }
+
+    fn candidates_with(zone: Zone, fallback: ZoneFallback) -> impl Iterator<Item = Zone> {
+        Zone::ALL[..=zone.index()]
+            .iter()
+            .rev()
+            .copied()
+            .filter(move |&candidate| match fallback {
+                ZoneFallback::Strict => candidate == zone,
+                ZoneFallback::Lower => true,
+                ZoneFallback::LowerExceptDma => candidate == zone || candidate != Zone::Dma,
+            })
+    }
+
+    /// Frees frames returned by [`ZonedFrameAllocator::alloc`].
+    pub fn dealloc(&mut self, frame: usize, count: usize) {
+        let zone = self.layout.zone_of(frame);
+        self.zones[zone.index()].dealloc(frame, count);
+    }
+
+    /// Frees frames returned by [`ZonedFrameAllocator::alloc_aligned`].
+    pub fn dealloc_aligned(&mut self, frame: usize, count: usize) {
+        let zone = self.layout.zone_of(frame);
+        self.zones[zone.index()].dealloc_aligned(frame, count);
+    }
+
+    /// Returns the allocator of `zone`, e.g. for its statistics.
+    pub fn zone(&self, zone: Zone) -> &BuddyAllocator<S> {
+        &self.zones[zone.index()]
+    }
+
+    pub fn layout(&self) -> ZoneLayout {
+        self.layout
+    }
+
+    pub fn fallback(&self) -> ZoneFallback {
+        self.fallback
+    }
+
+    /// Changes the fallback policy.
+    pub fn set_fallback(&mut self, fallback: ZoneFallback) {
+        self.fallback = fallback;
+    }
+
+    /// Returns the number of frames handed out from all zones.
+    pub fn stats_allocated(&self) -> usize {
+        self.zones.iter().map(|zone| zone.stats_allocated()).sum()
+    }
+
+    /// Returns the number of frames inserted into all zones.
+    pub fn stats_total(&self) -> usize {
+        self.zones.iter().map(|zone| zone.stats_total()).sum()
+    }
+}
@@ -0,0 +1,243 @@
+use core::alloc::Layout;
+use core::fmt;
+use core::ops::Range;
+
+/// Why an allocation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocError {
+    /// No memory has been added to the heap yet.
+    Uninitialized { layout: Layout },
+    /// The request can never be satisfied, because it is bigger than the largest block the
+    /// heap is able to manage (`max_size`).
+    TooLarge { layout: Layout, max_size: usize },
+    /// A free block is big enough, but none of them can satisfy the alignment.
+    Alignment { layout: Layout, largest_free: usize },
+    /// Not enough contiguous free memory is left.
+    OutOfMemory { layout: Layout, largest_free: usize },
+    /// A free block is big enough, but taking it would leave less than the `min` watermark
+    /// (`free` bytes are free). Only critical allocations may use that reserve.
+    BelowWatermark { layout: Layout, free: usize },
+}
+
+impl AllocError {
+    /// Returns the layout of the failed request.
+    pub fn layout(&self) -> Layout {
+        match *self {
+            AllocError::Uninitialized { layout }
+            | AllocError::TooLarge { layout, .. }
+            | AllocError::Alignment { layout, .. }
+            | AllocError::OutOfMemory { layout, .. }
+            | AllocError::BelowWatermark { layout, .. } => layout,
+        }
+    }
+
+    /// Returns the size of the largest free block at the time of the failure, or 0 if the
+    /// failure does not depend on it.
+    pub fn largest_free(&self) -> usize {
+        match *self {
+            AllocError::Uninitialized { .. }
+            | AllocError::TooLarge { .. }
+            | AllocError::BelowWatermark { .. } => 0,
+            AllocError::Alignment { largest_free, .. }
+            | AllocError::OutOfMemory { largest_free, .. } => largest_free,
+        }
+    }
+
+    /// Returns the same error for a different request. Used by front ends whose backing
+    /// allocation differs from what the caller asked for.
+    pub fn with_layout(self, layout: Layout) -> Self {
+        match self {
+            AllocError::Uninitialized { .. } => AllocError::Uninitialized { layout },
+            AllocError::TooLarge { max_size, .. } => AllocError::TooLarge { layout, max_size },
+            AllocError::Alignment { largest_free, .. } => {
+                AllocError::Alignment { layout, largest_free }
+            }
+            AllocError::OutOfMemory { largest_free, .. } => {
+                AllocError::OutOfMemory { layout, largest_free }
+            }
+            AllocError::BelowWatermark { free, .. } => AllocError::BelowWatermark { layout, free },
+        }
+    }
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let layout = self.layout();
+        match *self {
+            AllocError::Uninitialized { .. } => write!(
+                f,
+                "allocation of {} bytes (align {}) from an uninitialized heap",
+                layout.size(),
+                layout.align()
+            ),
+            AllocError::TooLarge { max_size, .. } => write!(
+                f,
+                "allocation of {} bytes (align {}) exceeds the maximum block size of {} bytes",
+                layout.size(),
+                layout.align(),
+                max_size
+            ),
+            AllocError::Alignment { largest_free, .. } => write!(
+                f,
+                "no free block can satisfy align {} for {} bytes (largest free block: {} bytes)",
+                layout.align(),
+                layout.size(),
+                largest_free
+            ),
+            AllocError::OutOfMemory { largest_free, .. } => write!(
+                f,
+                "out of memory allocating {} bytes (align {}), largest free block: {} bytes",
+                layout.size(),
+                layout.align(),
+                largest_free
+            ),
+            AllocError::BelowWatermark { free, .. } => write!(
+                f,
+                "allocation of {} bytes (align {}) refused below the min watermark ({} bytes free)",
+                layout.size(),
+                layout.align(),
+                free
+            ),
+        }
+    }
+}
+
+/// Why a range could not be removed from a heap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoveError {
+    /// The range is not inside a single region of the heap.
+    NotInHeap { range: Range<usize> },
+    /// `live` bytes of the range are still allocated. `first` is the first live block; the
+    /// heap's `live_blocks` lists all of them.
+    InUse { live: usize, first: Range<usize> },
+    /// Removing the range would split a region in two, but the region table is full.
+    TooManyRegions,
+}
+
+impl fmt::Display for RemoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RemoveError::NotInHeap { range } => write!(
+                f,
+                "range {:#x}..{:#x} is not inside a region of the heap",
+                range.start, range.end
+            ),
+            RemoveError::InUse { live, first } => write!(
+                f,
+                "{} bytes of the range are still in use, first live block at {:#x}..{:#x}",
+                live, first.start, first.end
+            ),
+            RemoveError::TooManyRegions => write!(f, "the region table of the heap is full"),
+        }
+    }
+}
+
+/// A broken invariant found by the `check_invariants` method of a heap. Addresses are those of
+/// the free blocks or holes involved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantError {
+    /// A hole starts below the hole before it in the list.
+    HoleOrder { previous: Range<usize>, hole: Range<usize> },
+    /// A hole starts before the end of the hole before it.
+    HoleOverlap { previous: Range<usize>, hole: Range<usize> },
+    /// A hole starts right where the hole before it ends, so the two should have been merged.
+    UncoalescedHoles { previous: Range<usize>, hole: Range<usize> },
+    /// A hole is smaller than the smallest hole the heap can manage.
+    HoleTooSmall { hole: Range<usize>, min_size: usize },
+    /// A hole is not inside a single region of the heap.
+    HoleOutOfBounds { hole: Range<usize> },
+    /// A free block of `order` is not aligned to `2^order`.
+    MisalignedBlock { block: usize, order: usize },
+    /// A free block and its buddy are both free at `order` but were not merged.
+    UnmergedBuddies { block: usize, buddy: usize, order: usize },
+    /// The free lists hold `found` bytes, but the counters of the heap say `expected`.
+    FreeMismatch { expected: usize, found: usize },
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvariantError::HoleOrder { previous, hole } => write!(
+                f,
+                "hole {:#x}..{:#x} comes after hole {:#x}..{:#x} but starts below it",
+                hole.start, hole.end, previous.start, previous.end
+            ),
+            InvariantError::HoleOverlap { previous, hole } => write!(
+                f,
+                "hole {:#x}..{:#x} overlaps hole {:#x}..{:#x}",
+                hole.start, hole.end, previous.start, previous.end
+            ),
+            InvariantError::UncoalescedHoles { previous, hole } => write!(
+                f,
+                "adjacent holes {:#x}..{:#x} and {:#x}..{:#x} were not merged",
+                previous.start, previous.end, hole.start, hole.end
+            ),
+            InvariantError::HoleTooSmall { hole, min_size } => write!(
+                f,
+                "hole {:#x}..{:#x} is smaller than the minimum of {} bytes",
+                hole.start, hole.end, min_size
+            ),
+            InvariantError::HoleOutOfBounds { hole } => write!(
+                f,
+                "hole {:#x}..{:#x} is not inside a region of the heap",
+                hole.start, hole.end
+            ),
+            InvariantError::MisalignedBlock { block, order } => write!(
+                f,
+                "free block {:#x} of order {} is not aligned to {:#x}",
+                block,
+                order,
+                1usize << order
+            ),
+            InvariantError::UnmergedBuddies { block, buddy, order } => write!(
+                f,
+                "free blocks {:#x} and {:#x} of order {} are buddies but were not merged",
+                block, buddy, order
+            ),
+            InvariantError::FreeMismatch { expected, found } => write!(
+                f,
+                "the free lists hold {} bytes, but {} bytes should be free",
+                found, expected
+            ),
+        }
+    }
+}
+
+/// An invalid call of the buddy `Heap::dealloc`, caught by the `debug_checks` feature.
+#[cfg(feature = "debug_checks")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidFree {
+    /// `ptr` is not the start of a live allocation, most likely because it was freed already.
+    DoubleFree { ptr: usize, layout: Layout },
+    /// `ptr` is a live allocation, but not of the size `layout` asks for.
+    WrongLayout { ptr: usize, layout: Layout },
+    /// `ptr` is not inside any region added to the heap.
+    OutsideHeap { ptr: usize, layout: Layout },
+}
+
+#[cfg(feature = "debug_checks")]
+impl fmt::Display for InvalidFree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvalidFree::DoubleFree { ptr, layout } => write!(
+                f,
+                "invalid deallocation of {:#x} ({} bytes): not allocated, probably a double free",
+                ptr,
+                layout.size()
+            ),
+            InvalidFree::WrongLayout { ptr, layout } => write!(
+                f,
+                "invalid deallocation of {:#x}: the allocation is not {} bytes aligned to {}",
+                ptr,
+                layout.size(),
+                layout.align()
+            ),
+            InvalidFree::OutsideHeap { ptr, layout } => write!(
+                f,
+                "invalid deallocation of {:#x} ({} bytes): outside every region of the heap",
+                ptr,
+                layout.size()
+            ),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+/// Number of size buckets of a [`FragmentationReport`], one per power of two.
+pub const FRAGMENTATION_BUCKETS: usize = usize::BITS as usize;
+
+/// A snapshot of the free memory of a heap, built from its free lists without allocating.
+///
+/// Free blocks are counted in power-of-two buckets: bucket `k` holds the blocks whose size is
+/// in `[2^k, 2^(k+1))`. For the buddy allocators this is exactly the order of the block. Sizes
+/// are in the unit of the heap: bytes, or frames for `BuddyAllocator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentationReport {
+    /// Number of free blocks per bucket.
+    pub free_blocks: [usize; FRAGMENTATION_BUCKETS],
+    /// Total size of the free blocks.
+    pub free: usize,
+    /// Size of the largest free block.
+    pub largest_free: usize,
+    /// Memory handed out beyond what callers asked for (`allocated - user`), as far as the heap
+    /// tracks it.
+    pub internal_waste: usize,
+}
+
+impl FragmentationReport {
+    #[cfg(any(feature = "buddy", feature = "linked_list"))]
+    pub(crate) const fn new(internal_waste: usize) -> Self {
+        FragmentationReport {
+            free_blocks: [0; FRAGMENTATION_BUCKETS],
+            free: 0,
+            largest_free: 0,
+            internal_waste,
+        }
+    }
+
+    /// Records `count` free blocks of `size` each.
+    #[cfg(any(feature = "buddy", feature = "linked_list"))]
+    pub(crate) fn add_free(&mut self, size: usize, count: usize) {
+        if size == 0 || count == 0 {
+            return;
+        }
+        self.free_blocks[size.ilog2() as usize] += count;
+        self.free += size * count;
+        self.largest_free = self.largest_free.max(size);
+    }
+
+    /// Returns the total number of free blocks.
+    pub fn free_block_count(&self) -> usize {
+        self.free_blocks.iter().sum()
+    }
+
+    /// Returns the external fragmentation index of `order`, in thousandths, as Linux computes
+    /// it for `/sys/kernel/debug/extfrag`: close to 0 means a request for `2^order` fails for
+    /// lack of memory, close to 1000 means it fails because the free memory is split into
+    /// blocks that are too small. Returns `None` if a free block of `2^order` exists, i.e. the
+    /// request would not fail at all.
+    pub fn fragmentation_index(&self, order: usize) -> Option<usize> {
+        let requested = 1usize << order;
+        if self.largest_free >= requested {
+            return None;
+        }
+        let blocks = self.free_block_count();
+        if blocks == 0 {
+            return Some(0);
+        }
+        // 单个空闲块时结果可能为负,按0处理
+        Some(1000usize.saturating_sub((1000 + self.free * 1000 / requested) / blocks))
+    }
+}
@@ -1,3 +1,85 @@
-mod buddy_allocator;
-mod linked_list_allocator;
-mod slab_allocator;
\ No newline at end of file
+#![cfg_attr(
+    feature = "alloc_ref",
+    feature(allocator_api)
+)]
+#![no_std]
+#![allow(non_snake_case)]
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+pub mod error;
+pub mod fragmentation;
+pub mod mempool;
+pub mod raw_heap;
+#[cfg(feature = "buddy")]
+pub mod buddy_allocator;
+#[cfg(feature = "linked_list")]
+pub mod linked_list_allocator;
+#[cfg(feature = "slab")]
+pub mod slab_allocator;
+
+pub use error::{AllocError, InvariantError, RemoveError};
+#[cfg(feature = "debug_checks")]
+pub use error::InvalidFree;
+pub use fragmentation::FragmentationReport;
+pub use mempool::MemPool;
+pub use raw_heap::{HeapStats, RawHeap};
+#[cfg(feature = "use_spin")]
+pub use raw_heap::Locked;
+
+#[cfg(feature = "buddy")]
+pub use buddy_allocator::{
+    AllocFlags, BitmapFrameSets, BuddyAllocator, FrameSets, Heap as BuddyHeap, WatermarkLevel, Zone,
+    ZoneFallback, ZoneLayout, ZonedFrameAllocator,
+};
+#[cfg(all(feature = "buddy", feature = "use_spin"))]
+pub use buddy_allocator::{
+    LockedFrameAllocator, LockedHeap as LockedBuddyHeap, LockedHeapWithRescue, NumaFrameAllocator,
+    PerCpuFrameAllocator,
+};
+
+#[cfg(feature = "linked_list")]
+pub use linked_list_allocator::{FitPolicy, Heap as LinkedListHeap};
+#[cfg(all(feature = "linked_list", feature = "use_spin"))]
+pub use linked_list_allocator::LockedHeap as LockedLinkedListHeap;
+
+#[cfg(feature = "slab")]
+pub use slab_allocator::{Kmalloc, SlabCache, SlabHeap, KMALLOC_SIZES};
+#[cfg(all(feature = "slab", feature = "use_spin"))]
+pub use slab_allocator::{LockedKmalloc, LockedSlabHeap};
+
+/// Glob-importable set of the allocator types, e.g. `use memoryAllocator::prelude::*;`.
+pub mod prelude {
+    pub use crate::{
+        AllocError, FragmentationReport, HeapStats, InvariantError, MemPool, RawHeap, RemoveError,
+    };
+    #[cfg(feature = "debug_checks")]
+    pub use crate::InvalidFree;
+    #[cfg(feature = "use_spin")]
+    pub use crate::Locked;
+
+    #[cfg(feature = "buddy")]
+    pub use crate::{
+        AllocFlags, BitmapFrameSets, BuddyAllocator, BuddyHeap, FrameSets, WatermarkLevel, Zone,
+        ZoneFallback, ZoneLayout, ZonedFrameAllocator,
+    };
+    #[cfg(all(feature = "buddy", feature = "use_spin"))]
+    pub use crate::{
+        LockedBuddyHeap, LockedFrameAllocator, LockedHeapWithRescue, NumaFrameAllocator,
+        PerCpuFrameAllocator,
+    };
+
+    #[cfg(feature = "linked_list")]
+    pub use crate::{FitPolicy, LinkedListHeap};
+    #[cfg(all(feature = "linked_list", feature = "use_spin"))]
+    pub use crate::LockedLinkedListHeap;
+
+    #[cfg(feature = "slab")]
+    pub use crate::{Kmalloc, SlabCache, SlabHeap};
+    #[cfg(all(feature = "slab", feature = "use_spin"))]
+    pub use crate::{LockedKmalloc, LockedSlabHeap};
+}
@@ -1,12 +1,32 @@
 use core::alloc::Layout;
+use core::iter;
 use core::mem;
 use core::mem::{align_of,size_of};
+use core::ops::Range;
 use core::ptr::NonNull;
 
 use super::align_up;
+use super::Region;
+use crate::error::{AllocError, InvariantError};
+use crate::fragmentation::FragmentationReport;
+
+/// How a hole is picked for an allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitPolicy {
+    /// The first hole that is big enough.
+    FirstFit,
+    /// The smallest hole that is big enough.
+    BestFit,
+    /// The largest hole.
+    WorstFit,
+    /// The first hole that is big enough, starting behind the previous allocation.
+    NextFit,
+}
 
 pub struct HoleList{
     first: Hole, 
+    // next fit的游标,指向上一次分配的末尾
+    rover: usize,
 }
 
 impl HoleList {
@@ -17,7 +37,8 @@ impl HoleList {
             first:Hole{
                 size:0,
                 next:None,
-            }
+            },
+            rover: 0,
         }
     }
 
@@ -28,10 +49,17 @@ impl HoleList {
             first:Hole{
                 size:0,
                 next:None,
-            }
+            },
+            rover: 0,
         }
     }
 
+    /// Creates a `HoleList` that contains the given hole.
+    ///
+    /// # Safety
+    ///
+    /// The memory in `[list_addr, list_addr + list_size)` must be valid and unused, and the
+    /// list takes ownership of it.
     pub unsafe fn new(list_addr: usize,list_size: usize) -> HoleList{
         assert_eq!(size_of::<Hole>(),Self::min_size());
 
@@ -47,34 +75,173 @@ impl HoleList {
                 size:0,
                 next:Some(&mut *ptr),
             },
+            rover: 0,
          }
     }
 
+    /// Adds the memory in `[addr, addr + size)` to the list, merging it with adjacent holes.
+    /// Returns the number of bytes that became a hole, which is less than `size` if `addr` is
+    /// not aligned.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`HoleList::new`].
+    pub unsafe fn add_region(&mut self, addr: usize, size: usize) -> usize{
+        let aligned_addr = align_up(addr, align_of::<Hole>());
+        let size = size.saturating_sub(aligned_addr - addr);
+        if size < Self::min_size(){
+            return 0;
+        }
+        deallocate(&mut self.first, aligned_addr, size);
+        size
+    }
+
+    /// Takes `[start, end)` out of the hole that contains it. What is left of the hole on either
+    /// side stays a hole if it is big enough, and is dropped otherwise. Returns false if no
+    /// single hole contains the range.
+    pub fn remove_range(&mut self, start: usize, end: usize) -> bool{
+        let mut previous = &mut self.first;
+        let hole = loop {
+            let info = match previous.next.as_ref() {
+                Some(hole) => hole.info(),
+                None => return false,
+            };
+            if info.addr > start {
+                return false;
+            }
+            if info.addr + info.size > start {
+                break info;
+            }
+            previous = move_helper(previous).next.as_mut().unwrap();
+        };
+        if hole.addr + hole.size < end {
+            return false;
+        }
+        previous.next = previous.next.as_mut().unwrap().next.take();
+
+        // 把两侧剩下的部分放回链表
+        let front = start - hole.addr;
+        let back = hole.addr + hole.size - end;
+        if front >= Self::min_size() {
+            deallocate(&mut self.first, hole.addr, front);
+        }
+        if back >= Self::min_size() {
+            deallocate(&mut self.first, end, back);
+        }
+        true
+    }
+
+    /// Returns the number of free bytes in `[start, end)`.
+    pub fn free_in(&self, start: usize, end: usize) -> usize{
+        let mut free = 0;
+        let mut current = self.first.next.as_deref();
+        while let Some(hole) = current {
+            let info = hole.info();
+            let low = info.addr.max(start);
+            let high = (info.addr + info.size).min(end);
+            if low < high {
+                free += high - low;
+            }
+            current = hole.next.as_deref();
+        }
+        free
+    }
+
+    /// Returns the maximal ranges of `[start, end)` that are not covered by a hole.
+    pub fn live_blocks(&self, start: usize, end: usize) -> impl Iterator<Item = Range<usize>> + '_{
+        let mut cursor = start;
+        let mut current = self.first.next.as_deref();
+        iter::from_fn(move || {
+            while cursor < end {
+                match current.map(|hole| hole.info()) {
+                    Some(info) if info.addr + info.size <= cursor => {
+                        current = current.and_then(|hole| hole.next.as_deref());
+                    }
+                    Some(info) if info.addr <= cursor => {
+                        cursor = info.addr + info.size;
+                    }
+                    Some(info) => {
+                        let live = cursor..info.addr.min(end);
+                        cursor = info.addr;
+                        return Some(live);
+                    }
+                    None => {
+                        let live = cursor..end;
+                        cursor = end;
+                        return Some(live);
+                    }
+                }
+            }
+            None
+        })
+    }
+
     pub fn align_layout(layout:Layout) -> Layout{
         let mut size = layout.size();
         if size < Self::min_size(){
             size = Self::min_size();
         }
         let size = align_up(size,mem::align_of::<Hole>());
-        let layout = Layout::from_size_align(size, layout.align()).unwrap();
-        
-        layout
+        Layout::from_size_align(size, layout.align()).unwrap()
     }
 
     /// 在链表中找到合适的块,如果可以一个块可以容纳layout.size()分配大小的字节，并且拥有layout.align()就表示足够大。
     /// 如果在链表找到这样的块，就会从从中分配一个符合大小内存出去。
     /// 首次适应算法,时间复杂度是O(n)
-    pub fn alloc_first_fit(&mut self,layout:Layout) ->Result<(NonNull<u8>, Layout), ()>{
+    pub fn alloc_first_fit(&mut self,layout:Layout) ->Result<(NonNull<u8>, Layout), AllocError>{
+        self.alloc_with(FitPolicy::FirstFit, layout)
+    }
+
+    /// Allocates a block for `layout` from the hole chosen by `policy`. All policies split the
+    /// chosen hole in the same way as first fit. Every policy except first fit walks the whole
+    /// list before allocating, so they are slower but fragment less for some workloads.
+    pub fn alloc_with(&mut self, policy: FitPolicy, layout: Layout) -> Result<(NonNull<u8>, Layout), AllocError>{
         let aligned_layout = Self::align_layout(layout);
 
-        allocate_first_fit(&mut self.first, aligned_layout).map(|holeinfo| {
-            (
-                NonNull::new(holeinfo.addr as *mut u8).unwrap(),
-                aligned_layout,
-            )
-        })
+        let result = match policy {
+            FitPolicy::FirstFit => allocate_first_fit(&mut self.first, aligned_layout),
+            _ => match select_hole(&self.first, aligned_layout, policy, self.rover) {
+                Some(addr) => allocate_from(&mut self.first, addr, aligned_layout),
+                None => Err(()),
+            },
+        };
+        result
+            .map(|holeinfo| {
+                if policy == FitPolicy::NextFit {
+                    self.rover = holeinfo.addr + holeinfo.size;
+                }
+                (
+                    NonNull::new(holeinfo.addr as *mut u8).unwrap(),
+                    aligned_layout,
+                )
+            })
+            .map_err(|()| self.alloc_error(layout, aligned_layout))
     }
 
+    /// Tells why no hole could take `aligned_layout`: it is the alignment if some hole could
+    /// take the same size at the natural alignment of a hole, and a lack of memory otherwise,
+    /// e.g. when every hole that is large enough would leave a remainder too small for a hole.
+    fn alloc_error(&self, layout: Layout, aligned_layout: Layout) -> AllocError{
+        let largest_free = self.largest_hole();
+        let natural = Layout::from_size_align(aligned_layout.size(), align_of::<Hole>()).unwrap();
+        if aligned_layout.align() > natural.align() {
+            let mut current = self.first.next.as_deref();
+            while let Some(hole) = current {
+                if split_hole(hole.info(), natural).is_some() {
+                    return AllocError::Alignment { layout, largest_free };
+                }
+                current = hole.next.as_deref();
+            }
+        }
+        AllocError::OutOfMemory { layout, largest_free }
+    }
+
+    /// Frees the allocation given by `ptr` and `layout` and returns the aligned layout that
+    /// was actually freed.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a pointer returned by a call to `alloc_first_fit` with identical layout.
     pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>,layout:Layout) -> Layout{
         let aligned_layout = Self::align_layout(layout);
         deallocate(
@@ -85,6 +252,109 @@ impl HoleList {
         aligned_layout
     }
 
+    /// Grows the allocation given by `ptr` and `layout` to `new_size` bytes by taking memory from
+    /// the hole right behind it. Returns the new aligned layout, or `None` if there is no such
+    /// hole or it is too small, in which case the allocation is left untouched.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a pointer returned by a call to `alloc_first_fit` with identical layout,
+    /// and `new_size` must not be smaller than `layout.size()`.
+    pub unsafe fn grow_in_place(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Option<Layout>{
+        let old_layout = Self::align_layout(layout);
+        let new_layout = Self::align_layout(Layout::from_size_align(new_size, layout.align()).ok()?);
+        debug_assert!(new_layout.size() >= old_layout.size());
+        if new_layout.size() == old_layout.size() {
+            return Some(new_layout);
+        }
+        let end = ptr.as_ptr() as usize + old_layout.size();
+        if grow_into_next(&mut self.first, end, new_layout.size() - old_layout.size()) {
+            Some(new_layout)
+        } else {
+            None
+        }
+    }
+
+    /// Shrinks the allocation given by `ptr` and `layout` to `new_size` bytes and turns the tail
+    /// into a hole. Returns the new aligned layout, or `None` if the tail is smaller than a hole
+    /// and there is no hole right behind the allocation to merge it into.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a pointer returned by a call to `alloc_first_fit` with identical layout,
+    /// and `new_size` must not be larger than `layout.size()`.
+    pub unsafe fn shrink_in_place(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> Option<Layout>{
+        let old_layout = Self::align_layout(layout);
+        let new_layout = Self::align_layout(Layout::from_size_align(new_size, layout.align()).ok()?);
+        debug_assert!(new_layout.size() <= old_layout.size());
+        let tail = old_layout.size() - new_layout.size();
+        let addr = ptr.as_ptr() as usize + new_layout.size();
+        if tail == 0 {
+            return Some(new_layout);
+        }
+        if tail >= Self::min_size() {
+            deallocate(&mut self.first, addr, tail);
+        } else if !shrink_into_next(&mut self.first, addr, tail) {
+            return None;
+        }
+        Some(new_layout)
+    }
+
+    /// Returns the size of the largest hole, or 0 if there is none.
+    pub fn largest_hole(&self) -> usize{
+        let mut largest = 0;
+        let mut current = self.first.next.as_deref();
+        while let Some(hole) = current {
+            largest = largest.max(hole.size);
+            current = hole.next.as_deref();
+        }
+        largest
+    }
+
+    /// Walks the holes and checks that they are sorted by address, do not overlap, are not
+    /// adjacent to each other, are at least `min_size()` bytes and lie inside one of `regions`.
+    /// Returns the total size of the holes, or the first violation found. A cycle in the list
+    /// shows up as a hole out of order.
+    pub fn check_invariants(&self, regions: &[Region]) -> Result<usize, InvariantError>{
+        let mut free = 0;
+        let mut previous: Option<Range<usize>> = None;
+        let mut current = self.first.next.as_deref();
+        while let Some(hole) = current {
+            let start = hole as *const Hole as usize;
+            let range = start..start + hole.size;
+            if let Some(previous) = previous {
+                if range.start < previous.start {
+                    return Err(InvariantError::HoleOrder { previous, hole: range });
+                }
+                if range.start < previous.end {
+                    return Err(InvariantError::HoleOverlap { previous, hole: range });
+                }
+                if range.start == previous.end {
+                    return Err(InvariantError::UncoalescedHoles { previous, hole: range });
+                }
+            }
+            if hole.size < Self::min_size() {
+                return Err(InvariantError::HoleTooSmall { hole: range, min_size: Self::min_size() });
+            }
+            if !regions.iter().any(|region| region.start() <= range.start && range.end <= region.end()) {
+                return Err(InvariantError::HoleOutOfBounds { hole: range });
+            }
+            free += hole.size;
+            previous = Some(range);
+            current = hole.next.as_deref();
+        }
+        Ok(free)
+    }
+
+    /// Adds every hole to `report`.
+    pub fn add_to_report(&self, report: &mut FragmentationReport){
+        let mut current = self.first.next.as_deref();
+        while let Some(hole) = current {
+            report.add_free(hole.size, 1);
+            current = hole.next.as_deref();
+        }
+    }
+
     // 返回最小分配尺寸,用于分配或者回收
     pub fn min_size() -> usize{
         size_of::<usize>() *2
@@ -184,8 +454,8 @@ fn split_hole(hole: HoleInfo, required_layout: Layout) -> Option<Allocation> {
             addr: aligned_hole.addr,
             size: required_size,
         },
-        front_padding: front_padding,
-        back_padding: back_padding,
+        front_padding,
+        back_padding,
     })
 }
 
@@ -194,35 +464,11 @@ fn allocate_first_fit(mut previous: &mut Hole, layout: Layout) -> Result<HoleInf
              let allocation: Option<Allocation> = previous
              .next
              .as_mut()
-             .and_then(|current| split_hole(current.info(), layout.clone()));
+             .and_then(|current| split_hole(current.info(), layout));
             
              match allocation {
                  Some(allocation) => {
-                     previous.next = previous.next.as_mut().unwrap().next.take();
-                    if let Some(padding) = allocation.front_padding{
-                         let ptr = padding.addr as *mut Hole;
-                         unsafe{
-                             ptr.write(Hole{
-                                 size:padding.size,
-                                 next:previous.next.take(),
-                             })
-                         }
-                         previous.next = Some(unsafe{&mut *ptr});
-                         previous = move_helper(previous).next.as_mut().unwrap();
-                     }
-                     if let Some(padding) = allocation.back_padding{
-                            let ptr = padding.addr as *mut Hole;
-                            unsafe{
-                                ptr.write(Hole{
-                                    size: padding.size,
-                                    next:previous.next.take(),
-                                })
-                            }
-                            previous.next = Some(unsafe {
-                                &mut *ptr
-                            });
-                     }
-                     return Ok(allocation.info);
+                     return Ok(take_allocation(previous, allocation));
                  }
                  None if previous.next.is_some() => {
                      previous = move_helper(previous).next.as_mut().unwrap();
@@ -234,6 +480,143 @@ fn allocate_first_fit(mut previous: &mut Hole, layout: Layout) -> Result<HoleInf
         }
 }
 
+/// Replaces the hole after `previous` by the front and back padding of `allocation`.
+fn take_allocation(mut previous: &mut Hole, allocation: Allocation) -> HoleInfo{
+    previous.next = previous.next.as_mut().unwrap().next.take();
+    if let Some(padding) = allocation.front_padding{
+        let ptr = padding.addr as *mut Hole;
+        unsafe{
+            ptr.write(Hole{
+                size:padding.size,
+                next:previous.next.take(),
+            })
+        }
+        previous.next = Some(unsafe{&mut *ptr});
+        previous = move_helper(previous).next.as_mut().unwrap();
+    }
+    if let Some(padding) = allocation.back_padding{
+        let ptr = padding.addr as *mut Hole;
+        unsafe{
+            ptr.write(Hole{
+                size: padding.size,
+                next:previous.next.take(),
+            })
+        }
+        previous.next = Some(unsafe {
+            &mut *ptr
+        });
+    }
+    allocation.info
+}
+
+/// Returns the address of the hole `policy` allocates `layout` from.
+fn select_hole(first: &Hole, layout: Layout, policy: FitPolicy, rover: usize) -> Option<usize>{
+    let mut chosen: Option<HoleInfo> = None;
+    // next fit: 游标之前第一个合适的hole,用于回绕
+    let mut wrapped: Option<HoleInfo> = None;
+    let mut current = first.next.as_deref();
+    while let Some(hole) = current {
+        let info = hole.info();
+        if split_hole(info, layout).is_some() {
+            match policy {
+                FitPolicy::FirstFit => return Some(info.addr),
+                FitPolicy::BestFit => {
+                    if chosen.is_none_or(|best| info.size < best.size) {
+                        chosen = Some(info);
+                    }
+                }
+                FitPolicy::WorstFit => {
+                    if chosen.is_none_or(|worst| info.size > worst.size) {
+                        chosen = Some(info);
+                    }
+                }
+                FitPolicy::NextFit => {
+                    if info.addr + info.size > rover {
+                        return Some(info.addr);
+                    }
+                    if wrapped.is_none() {
+                        wrapped = Some(info);
+                    }
+                }
+            }
+        }
+        current = hole.next.as_deref();
+    }
+    chosen.or(wrapped).map(|info| info.addr)
+}
+
+/// Allocates `layout` from the hole at `addr`.
+fn allocate_from(mut previous: &mut Hole, addr: usize, layout: Layout) -> Result<HoleInfo,()>{
+    loop{
+        match previous.next.as_ref().map(|hole| hole.info()) {
+            Some(info) if info.addr == addr => {
+                let allocation = split_hole(info, layout).ok_or(())?;
+                return Ok(take_allocation(previous, allocation));
+            }
+            Some(_) => {
+                previous = move_helper(previous).next.as_mut().unwrap();
+            }
+            None => {
+                return Err(());
+            }
+        }
+    }
+}
+
+/// Returns the last hole in front of `addr`, so that its successor is the first hole at or
+/// behind `addr`.
+fn previous_hole(mut previous: &mut Hole, addr: usize) -> &mut Hole{
+    while previous.next.as_ref().is_some_and(|next| next.info().addr < addr) {
+        previous = move_helper(previous).next.as_mut().unwrap();
+    }
+    previous
+}
+
+/// Takes `extra` bytes from the front of the hole starting at `end`.
+fn grow_into_next(first: &mut Hole, end: usize, extra: usize) -> bool{
+    let previous = previous_hole(first, end);
+    let next = match previous.next.as_ref().map(|hole| hole.info()) {
+        Some(info) if info.addr == end => info,
+        _ => return false,
+    };
+    if next.size == extra {
+        previous.next = previous.next.as_mut().unwrap().next.take();
+    } else if next.size >= extra + HoleList::min_size() {
+        // 剩下的部分作为新的hole,向后移动extra字节
+        let rest = previous.next.as_mut().unwrap().next.take();
+        let ptr = (end + extra) as *mut Hole;
+        unsafe {
+            ptr.write(Hole{
+                size: next.size - extra,
+                next: rest,
+            });
+        }
+        previous.next = Some(unsafe { &mut *ptr });
+    } else {
+        return false;
+    }
+    true
+}
+
+/// Merges the `tail` bytes at `addr` into the hole starting right behind them.
+fn shrink_into_next(first: &mut Hole, addr: usize, tail: usize) -> bool{
+    let previous = previous_hole(first, addr + tail);
+    let next = match previous.next.as_ref().map(|hole| hole.info()) {
+        Some(info) if info.addr == addr + tail => info,
+        _ => return false,
+    };
+    let rest = previous.next.as_mut().unwrap().next.take();
+    let ptr = addr as *mut Hole;
+    unsafe {
+        ptr.write(Hole{
+            size: next.size + tail,
+            next: rest,
+        });
+    }
+    previous.next = Some(unsafe { &mut *ptr });
+    true
+}
+
 fn deallocate(mut hole:&mut Hole, addr: usize, mut size: usize){
      loop {
          assert!(size >= HoleList::min_size());
@@ -297,7 +680,7 @@ fn deallocate(mut hole:&mut Hole, addr: usize, mut size: usize){
                 // after:   ___XXX__FFFF___    where F is the freed block
 
                 let new_hole = Hole{
-                    size:size,
+                    size,
                     next:hole.next.take(), // the reference to the Y block (if it exists)
                 };
 
@@ -1,41 +1,39 @@
-#![cfg_attr(feature = "const_mut_refs", feature(const_mut_refs))]
-#![cfg_attr(
-    feature = "alloc_ref",
-    feature(allocator_api, alloc_layout_extra, nonnull_slice_from_raw_parts)
-)]
-#![no_std]
-
-#[cfg(test)]
-#[macro_use]
-extern crate std;
-
+use core::alloc::Layout;
 #[cfg(feature = "use_spin")]
-extern crate spinning_top;
-
-#[cfg(feature = "use_spin")]
-use core::alloc::{Layout, GlobalAlloc};
+use core::alloc::GlobalAlloc;
 #[cfg(feature = "alloc_ref")]
-use core::alloc::{AllocError, Allocator};
+use core::alloc::Allocator;
+#[cfg(feature = "use_spin")]
+use core::marker::PhantomData;
 use core::mem::MaybeUninit;
 #[cfg(feature = "use_spin")]
 use core::ops::Deref;
+use core::ops::Range;
 use core::ptr::NonNull;
 
 #[cfg(test)]
 use linked_list::Hole;
 use linked_list::HoleList;
+pub use linked_list::FitPolicy;
 
 #[cfg(feature = "use_spin")]
 use spinning_top::Spinlock;
 
+use crate::error::{AllocError, InvariantError, RemoveError};
+use crate::fragmentation::FragmentationReport;
+use crate::raw_heap::{HeapStats, RawHeap};
+
 pub mod linked_list;
+mod region;
 #[cfg(test)]
 mod test;
 
+pub use region::*;
+
 pub struct  Heap{
-    bottom: usize,
-    size: usize,
-    used: usize,
+    // 堆所管理的不连续内存区域
+    regions: [Region; MAX_REGIONS],
+    region_count: usize,
     linkedlist: HoleList,
 }
 
@@ -43,9 +41,8 @@ impl Heap {
     #[cfg(not(feature = "const_mut_refs"))]
     pub fn empty() -> Heap{
         Heap{
-            bottom:0,
-            size:0,
-            used:0,
+            regions: [Region::empty(); MAX_REGIONS],
+            region_count: 0,
             linkedlist: HoleList::empty(),
         }
     }
@@ -53,24 +50,125 @@ impl Heap {
     #[cfg(feature = "const_mut_refs")]
     pub const fn empty() -> Heap{
         Heap{
-            bottom: 0,
-            size: 0,
-            used: 0,
+            regions: [Region::empty(); MAX_REGIONS],
+            region_count: 0,
             linkedlist: HoleList::empty(),
         }
     }
 
     /// Initializes an empty heap
     ///
-    /// # Unsafety
+    /// # Safety
     ///
     /// This function must be called at most once and must only be used on an
     /// empty heap.
     pub unsafe fn init(&mut self, heap_bottom: usize, heap_size: usize){
-        self.bottom = heap_bottom;
-        self.size = heap_size;
-        self.used = 0;
-        self.linkedlist = HoleList::new(heap_bottom, heap_size);
+        *self = Self::empty();
+        self.add_region(heap_bottom, heap_size);
+    }
+
+    /// Adds the memory in `[start, start + size)` to the heap. The region may lie anywhere; its
+    /// memory is merged into the hole list in address order. A region that touches an existing
+    /// region, below or above, is merged with it, since the holes on both sides of the boundary
+    /// join anyway; any other region takes a new slot of the region table. Regions smaller than
+    /// the minimal hole size are ignored. Bytes that can not become part of a hole because of
+    /// alignment are counted as used.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be valid and must not be used for anything else.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the region overlaps a region of the heap, or if the heap already
+    /// has [`MAX_REGIONS`] regions.
+    pub unsafe fn add_region(&mut self, start: usize, size: usize){
+        if size < HoleList::min_size(){
+            return;
+        }
+        assert!(
+            !self.regions().iter().any(|region| region.overlaps(start, size)),
+            "the region overlaps the heap"
+        );
+        let count = self.region_count;
+        let below = self.regions[..count].iter().position(|region| region.end() == start);
+        let above = self.regions[..count].iter().position(|region| region.start() == start + size);
+        // 先检查区域表,避免内存进了空闲链表却没有区域记录它
+        assert!(
+            below.is_some() || above.is_some() || count < MAX_REGIONS,
+            "too many heap regions"
+        );
+        let unusable = size - self.linkedlist.add_region(start, size);
+
+        match (below, above) {
+            // 正好填满两个区域之间的空隙,三块合成一块
+            (Some(below), Some(above)) => {
+                let upper = self.regions[above];
+                self.regions[below].grow(size + upper.size());
+                self.regions[below].add_used(unusable + upper.used());
+                self.regions.copy_within(above + 1..count, above);
+                self.region_count -= 1;
+                return;
+            }
+            (Some(below), None) => {
+                self.regions[below].grow(size);
+                self.regions[below].add_used(unusable);
+                return;
+            }
+            (None, Some(above)) => {
+                self.regions[above].grow_down(size);
+                self.regions[above].add_used(unusable);
+                return;
+            }
+            (None, None) => {}
+        }
+        self.regions[count] = Region::new(start, size, unusable);
+        self.region_count += 1;
+    }
+
+    /// Removes `[start, end)` from the heap if no byte of it is allocated, so that the memory
+    /// can be handed back, e.g. to a hypervisor. The range must lie inside a single region.
+    /// Removing the middle of a region splits it in two. Free bytes next to the range that are
+    /// too small to stay a hole are lost and counted as used.
+    pub fn try_remove_region(&mut self, start: usize, end: usize) -> Result<(), RemoveError>{
+        let index = self
+            .regions()
+            .iter()
+            .position(|region| region.start() <= start && start < end && end <= region.end())
+            .ok_or(RemoveError::NotInHeap { range: start..end })?;
+
+        if let Some(first) = self.live_blocks(start, end).next() {
+            let live = self.live_blocks(start, end).map(|block| block.len()).sum();
+            return Err(RemoveError::InUse { live, first });
+        }
+
+        let region = self.regions[index];
+        let left = region.start()..start;
+        let right = end..region.end();
+        if !left.is_empty() && !right.is_empty() && self.region_count == MAX_REGIONS {
+            return Err(RemoveError::TooManyRegions);
+        }
+        let removed = self.linkedlist.remove_range(start, end);
+        debug_assert!(removed, "a free range must lie inside one hole");
+
+        // 用两侧剩下的部分替换原来的区域,保持区域表的顺序
+        let rests = [left, right].into_iter().filter(|rest| !rest.is_empty());
+        let mut replaced = [Region::empty(); 2];
+        let mut count = 0;
+        for rest in rests {
+            let used = rest.len() - self.linkedlist.free_in(rest.start, rest.end);
+            replaced[count] = Region::new(rest.start, rest.len(), used);
+            count += 1;
+        }
+        self.regions.copy_within(index + 1..self.region_count, index + count);
+        self.regions[index..index + count].copy_from_slice(&replaced[..count]);
+        self.region_count = self.region_count - 1 + count;
+        Ok(())
+    }
+
+    /// Returns the maximal allocated ranges inside `[start, end)`.
+    pub fn live_blocks(&self, start: usize, end: usize) -> impl Iterator<Item = Range<usize>> + '_{
+        self.linkedlist.live_blocks(start, end)
     }
 
         /// Initialize an empty heap with provided memory.
@@ -88,7 +186,7 @@ impl Heap {
     ///
     /// This method panics if the heap is already initialized.
     pub fn init_from_slice(&mut self, mem: &'static mut[MaybeUninit<u8>]){
-        assert!(self.bottom == 0, "The heap has already been initialized.");
+        assert!(self.region_count == 0, "The heap has already been initialized.");
         let size = mem.len();
         let address = mem.as_ptr() as usize;
         // SAFETY: All initialization requires the bottom address to be valid, which implies it
@@ -101,19 +199,16 @@ impl Heap {
 
     /// Creates a new heap with the given `bottom` and `size`. The bottom address must be valid
     /// and the memory in the `[heap_bottom, heap_bottom + heap_size)` range must not be used for
-    /// anything else. This function is unsafe because it can cause undefined behavior if the
-    /// given address is invalid.
+    /// anything else.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because it can cause undefined behavior if the given address
+    /// is invalid.
     pub unsafe fn new(heap_bottom: usize,heap_size: usize) -> Heap{
-        if heap_size < HoleList::min_size(){
-            Self::empty()
-        }else{
-            Heap{
-                bottom: heap_bottom,
-                size: heap_size,
-                used: 0,
-                linkedlist: HoleList::new(heap_bottom, heap_size),
-            }
-        }
+        let mut heap = Self::empty();
+        heap.add_region(heap_bottom, heap_size);
+        heap
     }
 
      /// Creates a new heap from a slice of raw memory.
@@ -134,10 +229,22 @@ impl Heap {
     /// This function scans the list of free memory blocks and uses the first block that is big
     /// enough. The runtime is in O(n) where n is the number of free blocks, but it should be
     /// reasonably fast for small allocations.
-    pub fn allocate_first_fit(&mut self, layout:Layout) -> Result<NonNull<u8>,()>{
-        match self.linkedlist.alloc_first_fit(layout) {
+    pub fn allocate_first_fit(&mut self, layout:Layout) -> Result<NonNull<u8>, AllocError>{
+        self.allocate_with(FitPolicy::FirstFit, layout)
+    }
+
+    /// Allocates a chunk for `layout` from the hole chosen by `policy`, see [`FitPolicy`].
+    pub fn allocate_with(&mut self, policy: FitPolicy, layout:Layout) -> Result<NonNull<u8>, AllocError>{
+        if self.region_count == 0 {
+            return Err(AllocError::Uninitialized { layout });
+        }
+        let max_size = self.regions().iter().map(|region| region.size()).max().unwrap_or(0);
+        if layout.size() > max_size {
+            return Err(AllocError::TooLarge { layout, max_size });
+        }
+        match self.linkedlist.alloc_with(policy, layout) {
             Ok((ptr,aligned_layout)) =>{
-                self.used += aligned_layout.size();
+                self.region_mut(ptr).add_used(aligned_layout.size());
                 Ok(ptr)
             }
             Err(err) => Err(err),
@@ -145,60 +252,207 @@ impl Heap {
     }
 
     /// Frees the given allocation. `ptr` must be a pointer returned
-    /// by a call to the `allocate_first_fit` function with identical size and alignment. Undefined
-    /// behavior may occur for invalid arguments, thus this function is unsafe.
+    /// by a call to the `allocate_first_fit` function with identical size and alignment.
     ///
     /// This function walks the list of free memory blocks and inserts the freed block at the
     /// correct place. If the freed block is adjacent to another free block, the blocks are merged
     /// again. This operation is in `O(n)` since the list needs to be sorted by address.
+    ///
+    /// # Safety
+    ///
+    /// Undefined behavior may occur for invalid arguments, thus this function is unsafe.
     pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout:Layout){
-        self.used -= self.linkedlist.deallocate(ptr, layout).size();
+        let freed = self.linkedlist.deallocate(ptr, layout).size();
+        self.region_mut(ptr).sub_used(freed);
     }
 
-     /// Returns the bottom address of the heap.
-     pub fn bottom(&self) -> usize{
-         self.bottom
-     }
+    fn region_mut(&mut self, ptr: NonNull<u8>) -> &mut Region{
+        let addr = ptr.as_ptr() as usize;
+        self.regions[..self.region_count]
+            .iter_mut()
+            .find(|region| region.contains(addr))
+            .expect("address is outside of the heap")
+    }
+
+    /// Tries to grow the allocation at `ptr` to `new_size` bytes without moving it, by taking
+    /// memory from the free block right behind it. Returns false if that is not possible.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a pointer returned by an allocate function of this heap with identical
+    /// layout, and `new_size` must not be smaller than `layout.size()`.
+    pub unsafe fn grow_in_place(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> bool{
+        match self.linkedlist.grow_in_place(ptr, layout, new_size) {
+            Some(new_layout) => {
+                let grown = new_layout.size() - HoleList::align_layout(layout).size();
+                self.region_mut(ptr).add_used(grown);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Tries to shrink the allocation at `ptr` to `new_size` bytes without moving it. The freed
+    /// tail becomes a free block again. Returns false if the tail is too small to be freed on
+    /// its own and is not followed by a free block.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a pointer returned by an allocate function of this heap with identical
+    /// layout, and `new_size` must not be larger than `layout.size()`.
+    pub unsafe fn shrink_in_place(&mut self, ptr: NonNull<u8>, layout: Layout, new_size: usize) -> bool{
+        match self.linkedlist.shrink_in_place(ptr, layout, new_size) {
+            Some(new_layout) => {
+                let freed = HoleList::align_layout(layout).size() - new_layout.size();
+                self.region_mut(ptr).sub_used(freed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Resizes the allocation at `ptr` from `layout` to `new_layout`. The allocation is resized
+    /// in place if possible, otherwise it is moved to a new first fit block.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a pointer returned by an allocate function of this heap with identical
+    /// layout. On success the old pointer must not be used any more.
+    pub unsafe fn realloc(&mut self, ptr: NonNull<u8>, layout: Layout, new_layout: Layout) -> Result<NonNull<u8>, AllocError>{
+        self.realloc_with(FitPolicy::FirstFit, ptr, layout, new_layout)
+    }
+
+    /// Like [`Heap::realloc`], but a moved allocation is placed by `policy`.
+    ///
+    /// # Safety
+    ///
+    /// See [`Heap::realloc`].
+    pub unsafe fn realloc_with(&mut self, policy: FitPolicy, ptr: NonNull<u8>, layout: Layout, new_layout: Layout) -> Result<NonNull<u8>, AllocError>{
+        if (ptr.as_ptr() as usize).is_multiple_of(new_layout.align()) {
+            let in_place = if new_layout.size() >= layout.size() {
+                self.grow_in_place(ptr, layout, new_layout.size())
+            } else {
+                self.shrink_in_place(ptr, layout, new_layout.size())
+            };
+            if in_place {
+                return Ok(ptr);
+            }
+        }
+        let new_ptr = self.allocate_with(policy, new_layout)?;
+        core::ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.as_ptr(), layout.size().min(new_layout.size()));
+        self.deallocate(ptr, layout);
+        Ok(new_ptr)
+    }
+
+    /// Returns the regions of the heap, in the order they were added.
+    pub fn regions(&self) -> &[Region]{
+        &self.regions[..self.region_count]
+    }
 
     /// Returns the size of the heap.
     pub fn size(&self) -> usize{
-        self.size
+        self.regions().iter().map(|region| region.size()).sum()
     }
-     /// Return the top address of the heap
-     pub fn top(&self) ->usize{
-         self.bottom+self.size
-     }
      /// Returns the size of the used part of the heap
      pub fn used(&self) -> usize{
-         self.used
+         self.regions().iter().map(|region| region.used()).sum()
      }
      /// Returns the size of the free part of the heap
      pub fn free(&self) -> usize{
-         self.size-self.used
+         self.size()-self.used()
      }
 
-    /// Extends the size of the heap by creating a new hole at the end
+    /// Checks the hole list, see [`HoleList::check_invariants`], and that the holes add up to
+    /// the free bytes of the regions.
+    pub fn check_invariants(&self) -> Result<(), InvariantError>{
+        let found = self.linkedlist.check_invariants(self.regions())?;
+        let expected = self.free();
+        if found != expected {
+            return Err(InvariantError::FreeMismatch { expected, found });
+        }
+        Ok(())
+    }
+
+    /// Returns the holes per size bucket. The heap does not track requested sizes, so the
+    /// internal waste is 0; bytes lost to alignment count as used.
+    pub fn fragmentation(&self) -> FragmentationReport{
+        let mut report = FragmentationReport::new(0);
+        self.linkedlist.add_to_report(&mut report);
+        report
+    }
+
+    /// Extends the region added last by creating a new hole at its end
     ///
-    /// # Unsafety
+    /// # Safety
     ///
     /// The new extended area must be valid
     pub unsafe fn extend(&mut self, by: usize){
-        let top = self.top();
-        let layout = Layout::from_size_align(by, 1).unwrap();
-        self.linkedlist.deallocate(NonNull:: new_unchecked(top as *mut u8), layout);
-        self.size += by;
+        let end = self.regions().last().expect("the heap has no region to extend").end();
+        self.add_region(end, by);
+    }
+}
+
+impl RawHeap for Heap {
+    fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError>{
+        self.allocate_first_fit(layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout){
+        self.deallocate(ptr, layout)
+    }
+
+    unsafe fn add_region(&mut self, start: usize, size: usize){
+        Heap::add_region(self, start, size)
+    }
+
+    fn stats(&self) -> HeapStats{
+        HeapStats {
+            total: self.size(),
+            user: self.used(),
+            allocated: self.used(),
+        }
     }
 }
 
+/// Picks the [`FitPolicy`] of a `LockedHeap` at the type level.
+pub trait Placement {
+    const POLICY: FitPolicy;
+}
+
+/// Marker for [`FitPolicy::FirstFit`].
+pub struct FirstFit;
+/// Marker for [`FitPolicy::BestFit`].
+pub struct BestFit;
+/// Marker for [`FitPolicy::WorstFit`].
+pub struct WorstFit;
+/// Marker for [`FitPolicy::NextFit`].
+pub struct NextFit;
+
+impl Placement for FirstFit {
+    const POLICY: FitPolicy = FitPolicy::FirstFit;
+}
+
+impl Placement for BestFit {
+    const POLICY: FitPolicy = FitPolicy::BestFit;
+}
+
+impl Placement for WorstFit {
+    const POLICY: FitPolicy = FitPolicy::WorstFit;
+}
+
+impl Placement for NextFit {
+    const POLICY: FitPolicy = FitPolicy::NextFit;
+}
+
 #[cfg(all(feature = "alloc_ref", feature = "use_spin"))]
-unsafe impl Allocator for LockedHeap {
-    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+unsafe impl<P: Placement> Allocator for LockedHeap<P> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, core::alloc::AllocError> {
         if layout.size() == 0 {
-            return Ok(NonNull::slice_from_raw_parts(layout.dangling(), 0));
+            return Ok(NonNull::slice_from_raw_parts(layout.dangling_ptr(), 0));
         }
-        match self.0.lock().allocate_first_fit(layout) {
+        match self.0.lock().allocate_with(P::POLICY, layout) {
             Ok(ptr) => Ok(NonNull::slice_from_raw_parts(ptr, layout.size())),
-            Err(()) => Err(AllocError),
+            Err(_) => Err(core::alloc::AllocError),
         }
     }
 
@@ -207,41 +461,62 @@ unsafe impl Allocator for LockedHeap {
             self.0.lock().deallocate(ptr, layout);
         }
     }
+
+    unsafe fn grow(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, core::alloc::AllocError> {
+        if old_layout.size() == 0 {
+            return self.allocate(new_layout);
+        }
+        match self.0.lock().realloc_with(P::POLICY, ptr, old_layout, new_layout) {
+            Ok(ptr) => Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size())),
+            Err(_) => Err(core::alloc::AllocError),
+        }
+    }
+
+    unsafe fn shrink(&self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<NonNull<[u8]>, core::alloc::AllocError> {
+        if new_layout.size() == 0 {
+            self.deallocate(ptr, old_layout);
+            return Ok(NonNull::slice_from_raw_parts(new_layout.dangling_ptr(), 0));
+        }
+        match self.0.lock().realloc_with(P::POLICY, ptr, old_layout, new_layout) {
+            Ok(ptr) => Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size())),
+            Err(_) => Err(core::alloc::AllocError),
+        }
+    }
 }
 
+/// A locked `Heap` whose allocations use the placement policy `P`.
 #[cfg(feature="use_spin")]
-pub struct LockedHeap(Spinlock<Heap>);
+pub struct LockedHeap<P: Placement = FirstFit>(Spinlock<Heap>, PhantomData<P>);
 
 #[cfg(feature = "use_spin")]
-impl LockedHeap {
+impl<P: Placement> LockedHeap<P> {
     /// Creates an empty heap. All allocate calls will return `None`.
     #[cfg(feature = "use_spin_nightly")]
-    pub const fn empty() -> LockedHeap {
-        LockedHeap(Spinlock::new(Heap::empty()))
+    pub const fn empty() -> LockedHeap<P> {
+        LockedHeap(Spinlock::new(Heap::empty()), PhantomData)
     }
 
     /// Creates an empty heap. All allocate calls will return `None`.
     #[cfg(not(feature = "use_spin_nightly"))]
-    pub fn empty() -> LockedHeap {
-        LockedHeap(Spinlock::new(Heap::empty()))
+    pub fn empty() -> LockedHeap<P> {
+        LockedHeap(Spinlock::new(Heap::empty()), PhantomData)
     }
 
     /// Creates a new heap with the given `bottom` and `size`. The bottom address must be valid
     /// and the memory in the `[heap_bottom, heap_bottom + heap_size)` range must not be used for
-    /// anything else. This function is unsafe because it can cause undefined behavior if the
-    /// given address is invalid.
-    pub unsafe fn new(heap_bottom: usize, heap_size: usize) -> LockedHeap {
-        LockedHeap(Spinlock::new(Heap {
-            bottom: heap_bottom,
-            size: heap_size,
-            used: 0,
-            linkedlist:HoleList::new(heap_bottom,heap_size),
-        }))
+    /// anything else.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because it can cause undefined behavior if the given address
+    /// is invalid.
+    pub unsafe fn new(heap_bottom: usize, heap_size: usize) -> LockedHeap<P> {
+        LockedHeap(Spinlock::new(Heap::new(heap_bottom, heap_size)), PhantomData)
     }
 }
 
 #[cfg(feature = "use_spin")]
-impl Deref for LockedHeap {
+impl<P: Placement> Deref for LockedHeap<P> {
     type Target = Spinlock<Heap>;
 
     fn deref(&self) -> &Spinlock<Heap>{
@@ -250,13 +525,13 @@ impl Deref for LockedHeap {
 }
 
 #[cfg(feature = "use_spin")]
-unsafe impl GlobalAlloc for LockedHeap {
+unsafe impl<P: Placement> GlobalAlloc for LockedHeap<P> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8{
         self.0
         .lock()
-        .allocate_first_fit(layout)
+        .allocate_with(P::POLICY, layout)
         .ok()
-        .map_or(0 as *mut u8, |allocation| allocation.as_ptr())
+        .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
@@ -264,6 +539,15 @@ unsafe impl GlobalAlloc for LockedHeap {
             .lock()
             .deallocate(NonNull::new_unchecked(ptr), layout)
     }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        self.0
+            .lock()
+            .realloc_with(P::POLICY, NonNull::new_unchecked(ptr), layout, new_layout)
+            .ok()
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
 }
 
 /// Align downwards. Returns the greatest x with alignment `align`
@@ -0,0 +1,76 @@
+/// Maximum number of disjoint regions a `Heap` can manage.
+pub const MAX_REGIONS: usize = 8;
+
+/// A contiguous range of memory that was added to a `Heap`, with its own accounting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    start: usize,
+    size: usize,
+    used: usize,
+}
+
+impl Region {
+    pub(crate) const fn empty() -> Self {
+        Region {
+            start: 0,
+            size: 0,
+            used: 0,
+        }
+    }
+
+    pub(crate) fn new(start: usize, size: usize, used: usize) -> Self {
+        Region { start, size, used }
+    }
+
+    /// Returns the start address of the region.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Returns the size of the region.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the end address of the region (exclusive).
+    pub fn end(&self) -> usize {
+        self.start + self.size
+    }
+
+    /// Returns the number of bytes allocated from this region.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// Returns the number of free bytes in this region.
+    pub fn free(&self) -> usize {
+        self.size - self.used
+    }
+
+    /// Returns true if `addr` lies inside the region.
+    pub fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end()
+    }
+
+    /// Returns true if `[start, start + size)` shares at least one byte with the region.
+    pub fn overlaps(&self, start: usize, size: usize) -> bool {
+        start < self.end() && self.start < start + size
+    }
+
+    pub(crate) fn grow(&mut self, by: usize) {
+        self.size += by;
+    }
+
+    pub(crate) fn grow_down(&mut self, by: usize) {
+        self.start -= by;
+        self.size += by;
+    }
+
+    pub(crate) fn add_used(&mut self, bytes: usize) {
+        self.used += bytes;
+    }
+
+    pub(crate) fn sub_used(&mut self, bytes: usize) {
+        self.used -= bytes;
+    }
+}
@@ -0,0 +1,653 @@
+use super::*;
+use crate::error::{AllocError, InvariantError, RemoveError};
+use core::alloc::Layout;
+use std::mem::{align_of, size_of};
+use std::prelude::v1::*;
+
+fn new_heap() -> Heap {
+    const HEAP_SIZE: usize = 1000;
+    let heap_space = Box::leak(Box::new([MaybeUninit::<u8>::uninit(); HEAP_SIZE]));
+    let bottom = heap_space.as_ptr() as usize;
+    let heap = Heap::from_slice(heap_space);
+    assert_eq!(heap.regions()[0].start(), bottom);
+    assert_eq!(heap.size(), HEAP_SIZE);
+    heap
+}
+
+fn bottom(heap: &Heap) -> usize {
+    heap.regions()[0].start()
+}
+
+#[test]
+fn empty() {
+    let mut heap = Heap::empty();
+    let layout = Layout::from_size_align(1, 1).unwrap();
+    assert!(heap.allocate_first_fit(layout).is_err());
+}
+
+#[test]
+fn oom() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(heap.size() + 1, align_of::<usize>());
+    let addr = heap.allocate_first_fit(layout.unwrap());
+    assert!(addr.is_err());
+}
+
+#[test]
+fn allocate_double_usize() {
+    let mut heap = new_heap();
+    let size = size_of::<usize>() * 2;
+    let layout = Layout::from_size_align(size, align_of::<usize>());
+    let addr = heap.allocate_first_fit(layout.unwrap());
+    assert!(addr.is_ok());
+    let addr = addr.unwrap().as_ptr() as usize;
+    assert!(addr == bottom(&heap));
+    let (hole_addr, hole_size) = heap.linkedlist.first_hole().expect("ERROR: no hole left");
+    assert!(hole_addr == bottom(&heap) + size);
+    assert!(hole_size == heap.size() - size);
+
+    unsafe {
+        assert_eq!(
+            (*((addr + size) as *const Hole)).size,
+            heap.size() - size
+        );
+    }
+}
+
+#[test]
+fn allocate_and_free_double_usize() {
+    let mut heap = new_heap();
+
+    let layout = Layout::from_size_align(size_of::<usize>() * 2, align_of::<usize>()).unwrap();
+    let x = heap.allocate_first_fit(layout).unwrap();
+    unsafe {
+        *(x.as_ptr() as *mut (usize, usize)) = (0xdeafdeadbeafbabe, 0xdeafdeadbeafbabe);
+
+        heap.deallocate(x, layout);
+        assert_eq!((*(bottom(&heap) as *const Hole)).size, heap.size());
+        assert!((*(bottom(&heap) as *const Hole)).next.is_none());
+    }
+}
+
+#[test]
+fn deallocate_right_before() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(size_of::<usize>() * 5, 1).unwrap();
+
+    let x = heap.allocate_first_fit(layout).unwrap();
+    let y = heap.allocate_first_fit(layout).unwrap();
+    let z = heap.allocate_first_fit(layout).unwrap();
+
+    unsafe {
+        heap.deallocate(y, layout);
+        assert_eq!((*(y.as_ptr() as *const Hole)).size, layout.size());
+        heap.deallocate(x, layout);
+        assert_eq!((*(x.as_ptr() as *const Hole)).size, layout.size() * 2);
+        heap.deallocate(z, layout);
+        assert_eq!((*(x.as_ptr() as *const Hole)).size, heap.size());
+    }
+}
+
+#[test]
+fn used_and_free() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(size_of::<usize>() * 4, align_of::<usize>()).unwrap();
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.free(), heap.size());
+
+    let x = heap.allocate_first_fit(layout).unwrap();
+    assert_eq!(heap.used(), layout.size());
+    assert_eq!(heap.free(), heap.size() - layout.size());
+
+    unsafe {
+        heap.deallocate(x, layout);
+    }
+    assert_eq!(heap.used(), 0);
+}
+
+#[test]
+fn extend_heap() {
+    let space = Box::leak(Box::new([MaybeUninit::<u8>::uninit(); 2000]));
+    let mut heap = unsafe { Heap::new(space.as_ptr() as usize, 1000) };
+    assert_eq!(heap.size(), 1000);
+
+    unsafe {
+        heap.extend(1000);
+    }
+    assert_eq!(heap.size(), 2000);
+    let layout = Layout::from_size_align(1500, 1).unwrap();
+    assert!(heap.allocate_first_fit(layout).is_ok());
+}
+
+#[test]
+fn alloc_errors() {
+    let mut heap = Heap::empty();
+    let layout = Layout::from_size_align(8, 8).unwrap();
+    assert_eq!(
+        heap.allocate_first_fit(layout),
+        Err(AllocError::Uninitialized { layout })
+    );
+
+    let mut heap = new_heap();
+    let huge = Layout::from_size_align(heap.size() + 1, 1).unwrap();
+    assert_eq!(
+        heap.allocate_first_fit(huge),
+        Err(AllocError::TooLarge {
+            layout: huge,
+            max_size: heap.size()
+        })
+    );
+
+    // 剩下的hole足够大,但它的起始地址没有按16字节对齐
+    let x = Layout::from_size_align(heap.size() / 2 + 4, 1).unwrap();
+    let _x = heap.allocate_first_fit(x).unwrap();
+    let (hole_addr, _) = heap.linkedlist.first_hole().unwrap();
+    assert_eq!(hole_addr % 16, 8);
+    let unaligned = Layout::from_size_align(heap.free() - 16, 16).unwrap();
+    assert_eq!(
+        heap.allocate_first_fit(unaligned),
+        Err(AllocError::Alignment {
+            layout: unaligned,
+            largest_free: heap.free()
+        })
+    );
+
+    // hole足够大,但剩下的8字节不够组成新的hole,与对齐无关
+    let tight = Layout::from_size_align(heap.free() - 8, 8).unwrap();
+    assert_eq!(
+        heap.allocate_first_fit(tight),
+        Err(AllocError::OutOfMemory {
+            layout: tight,
+            largest_free: heap.free()
+        })
+    );
+
+    let rest = Layout::from_size_align(heap.free() + 8, 1).unwrap();
+    assert!(matches!(
+        heap.allocate_first_fit(rest),
+        Err(AllocError::OutOfMemory { .. })
+    ));
+}
+
+/// Leaves holes of 64, 128 and 32 bytes in front of the rest of the heap.
+fn fragmented_heap() -> (Heap, [usize; 3]) {
+    let mut heap = new_heap();
+    let mut holes = [0; 3];
+    let separator = Layout::from_size_align(16, 8).unwrap();
+    for (hole, size) in holes.iter_mut().zip([64, 128, 32]) {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        *hole = heap.allocate_first_fit(layout).unwrap().as_ptr() as usize;
+        heap.allocate_first_fit(separator).unwrap();
+    }
+    for (&hole, size) in holes.iter().zip([64, 128, 32]) {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        unsafe { heap.deallocate(NonNull::new(hole as *mut u8).unwrap(), layout) };
+    }
+    (heap, holes)
+}
+
+#[test]
+fn fit_policies() {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+
+    let (mut heap, [a, _, _]) = fragmented_heap();
+    let x = heap.allocate_with(FitPolicy::FirstFit, layout).unwrap();
+    assert_eq!(x.as_ptr() as usize, a);
+
+    let (mut heap, [_, _, c]) = fragmented_heap();
+    let x = heap.allocate_with(FitPolicy::BestFit, layout).unwrap();
+    assert_eq!(x.as_ptr() as usize, c);
+
+    let (mut heap, _) = fragmented_heap();
+    let x = heap.allocate_with(FitPolicy::WorstFit, layout).unwrap();
+    // 最大的是末尾剩下的hole
+    assert_eq!(x.as_ptr() as usize, bottom(&heap) + 64 + 16 + 128 + 16 + 32 + 16);
+
+    // 剩下的hole都装不下时,best fit和first fit一样报错
+    let (mut heap, _) = fragmented_heap();
+    let huge = Layout::from_size_align(heap.free(), 8).unwrap();
+    assert!(heap.allocate_with(FitPolicy::BestFit, huge).is_err());
+}
+
+#[test]
+fn next_fit() {
+    let (mut heap, [a, b, c]) = fragmented_heap();
+    let small = Layout::from_size_align(64, 8).unwrap();
+
+    // 第一次从头开始,之后从上一次分配的末尾继续
+    let x = heap.allocate_with(FitPolicy::NextFit, small).unwrap();
+    assert_eq!(x.as_ptr() as usize, a);
+    let y = heap.allocate_with(FitPolicy::NextFit, small).unwrap();
+    assert_eq!(y.as_ptr() as usize, b);
+
+    let z = heap.allocate_with(FitPolicy::NextFit, Layout::from_size_align(48, 8).unwrap()).unwrap();
+    assert_eq!(z.as_ptr() as usize, b + 64);
+    // b剩下的16字节放不下,继续往后找
+    let w = heap.allocate_with(FitPolicy::NextFit, Layout::from_size_align(32, 8).unwrap()).unwrap();
+    assert_eq!(w.as_ptr() as usize, c);
+
+    // 游标之后放不下时回绕到最前面
+    unsafe { heap.deallocate(x, small) };
+    let rest = Layout::from_size_align(heap.free() - 64 - 16, 8).unwrap();
+    heap.allocate_with(FitPolicy::NextFit, rest).unwrap();
+    let v = heap.allocate_with(FitPolicy::NextFit, small).unwrap();
+    assert_eq!(v.as_ptr() as usize, a);
+}
+
+#[cfg(feature = "use_spin")]
+#[test]
+fn locked_heap_policy() {
+    let (heap, [_, _, c]) = fragmented_heap();
+    let locked = LockedHeap::<BestFit>::empty();
+    *locked.lock() = heap;
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let x = unsafe { GlobalAlloc::alloc(&locked, layout) };
+    assert_eq!(x as usize, c);
+}
+
+#[test]
+fn grow_in_place() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let x = heap.allocate_first_fit(layout).unwrap();
+
+    // 后面就是剩余的hole
+    unsafe {
+        assert!(heap.grow_in_place(x, layout, 100));
+    }
+    assert_eq!(heap.used(), 104);
+    let (hole_addr, hole_size) = heap.linkedlist.first_hole().unwrap();
+    assert_eq!(hole_addr, x.as_ptr() as usize + 104);
+    assert_eq!(hole_size, heap.size() - 104);
+
+    // 后面的内存已经被使用
+    let grown = Layout::from_size_align(100, 8).unwrap();
+    let y = heap.allocate_first_fit(layout).unwrap();
+    unsafe {
+        assert!(!heap.grow_in_place(x, grown, 200));
+        heap.deallocate(y, layout);
+        assert!(heap.grow_in_place(x, grown, heap.size()));
+    }
+    assert_eq!(heap.free(), 0);
+    assert!(heap.linkedlist.first_hole().is_none());
+}
+
+#[test]
+fn shrink_in_place() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(128, 8).unwrap();
+    let x = heap.allocate_first_fit(layout).unwrap();
+    let y = heap.allocate_first_fit(layout).unwrap();
+    let z = heap.allocate_first_fit(layout).unwrap();
+
+    // 尾部足够大,成为新的hole
+    unsafe {
+        assert!(heap.shrink_in_place(x, layout, 64));
+    }
+    assert_eq!(heap.used(), 64 + 2 * 128);
+    let (hole_addr, hole_size) = heap.linkedlist.first_hole().unwrap();
+    assert_eq!(hole_addr, x.as_ptr() as usize + 64);
+    assert_eq!(hole_size, 64);
+
+    // 尾部只有8字节,后面也没有hole
+    let small = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        assert!(!heap.shrink_in_place(y, layout, 120));
+        assert!(heap.shrink_in_place(x, small, 56));
+        assert!(heap.shrink_in_place(y, layout, 64));
+    }
+    assert_eq!(heap.used(), 56 + 64 + 128);
+    let (hole_addr, hole_size) = heap.linkedlist.first_hole().unwrap();
+    assert_eq!(hole_addr, x.as_ptr() as usize + 56);
+    assert_eq!(hole_size, 72);
+
+    unsafe {
+        heap.deallocate(x, Layout::from_size_align(56, 8).unwrap());
+        heap.deallocate(y, small);
+        heap.deallocate(z, layout);
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.linkedlist.first_hole(), Some((bottom(&heap), heap.size())));
+}
+
+#[test]
+fn realloc_moves() {
+    let mut heap = new_heap();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let x = heap.allocate_first_fit(layout).unwrap();
+    let _y = heap.allocate_first_fit(layout).unwrap();
+    unsafe {
+        x.as_ptr().write_bytes(0xab, 32);
+        let new_layout = Layout::from_size_align(64, 8).unwrap();
+        let z = heap.realloc(x, layout, new_layout).unwrap();
+        assert_ne!(z, x);
+        assert!(core::slice::from_raw_parts(z.as_ptr(), 32).iter().all(|&b| b == 0xab));
+        assert_eq!(heap.used(), 64 + 32);
+
+        // 对齐要求变大时不能原地扩展
+        let aligned = Layout::from_size_align(128, 256).unwrap();
+        let w = heap.realloc(z, new_layout, aligned).unwrap();
+        assert_eq!(w.as_ptr() as usize % 256, 0);
+        assert!(core::slice::from_raw_parts(w.as_ptr(), 32).iter().all(|&b| b == 0xab));
+    }
+}
+
+#[cfg(feature = "use_spin")]
+#[test]
+fn locked_heap_realloc() {
+    let locked = LockedHeap::<FirstFit>::empty();
+    *locked.lock() = new_heap();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    unsafe {
+        let x = GlobalAlloc::alloc(&locked, layout);
+        let y = GlobalAlloc::realloc(&locked, x, layout, 256);
+        assert_eq!(x, y);
+        assert_eq!(locked.lock().used(), 256);
+        let z = GlobalAlloc::realloc(&locked, y, Layout::from_size_align(256, 8).unwrap(), 16);
+        assert_eq!(x, z);
+        assert_eq!(locked.lock().used(), 16);
+    }
+}
+
+#[test]
+fn multiple_regions() {
+    let space = Box::leak(Box::new([0usize; 512]));
+    let base = space.as_ptr() as usize;
+    let mut heap = Heap::empty();
+    unsafe {
+        // 两块不相邻的区域,高地址的先加入
+        heap.add_region(base + 2048, 1024);
+        heap.add_region(base, 512);
+    }
+    assert_eq!(heap.regions().len(), 2);
+    assert_eq!(heap.size(), 1536);
+    assert_eq!(heap.linkedlist.first_hole(), Some((base, 512)));
+
+    let small = Layout::from_size_align(256, 8).unwrap();
+    let large = Layout::from_size_align(768, 8).unwrap();
+    let x = heap.allocate_first_fit(small).unwrap();
+    let y = heap.allocate_first_fit(large).unwrap();
+    assert_eq!(x.as_ptr() as usize, base);
+    assert_eq!(y.as_ptr() as usize, base + 2048);
+    assert_eq!(heap.regions()[0].used(), 768);
+    assert_eq!(heap.regions()[1].used(), 256);
+    assert_eq!(heap.used(), 1024);
+
+    // 一次分配不能跨越两块区域
+    let huge = Layout::from_size_align(1025, 8).unwrap();
+    assert_eq!(
+        heap.allocate_first_fit(huge),
+        Err(AllocError::TooLarge { layout: huge, max_size: 1024 })
+    );
+
+    unsafe {
+        heap.deallocate(x, small);
+        heap.deallocate(y, large);
+    }
+    assert_eq!(heap.used(), 0);
+    assert_eq!(heap.regions()[1].free(), 512);
+}
+
+#[test]
+fn adjacent_region_extends() {
+    let space = Box::leak(Box::new([0usize; 256]));
+    let base = space.as_ptr() as usize;
+    let mut heap = unsafe { Heap::new(base, 1024) };
+    unsafe {
+        heap.add_region(base + 1024, 1024);
+    }
+    assert_eq!(heap.regions().len(), 1);
+    assert_eq!(heap.regions()[0].end(), base + 2048);
+    assert_eq!(heap.linkedlist.first_hole(), Some((base, 2048)));
+    assert!(heap.allocate_first_fit(Layout::from_size_align(2048, 8).unwrap()).is_ok());
+}
+
+#[test]
+fn adjacent_region_below() {
+    let space = Box::leak(Box::new([0usize; 1024]));
+    let base = space.as_ptr() as usize;
+    let mut heap = Heap::empty();
+    unsafe {
+        // 低地址的区域后加入,也要合并成一块
+        heap.add_region(base + 2048, 2048);
+        heap.add_region(base, 2048);
+    }
+    assert_eq!(heap.regions().len(), 1);
+    assert_eq!(heap.regions()[0].start(), base);
+    assert_eq!(heap.regions()[0].end(), base + 4096);
+    assert_eq!(heap.linkedlist.first_hole(), Some((base, 4096)));
+
+    // 跨越原来边界的分配记在同一个区域上
+    let small = Layout::from_size_align(1000, 8).unwrap();
+    let large = Layout::from_size_align(1504, 8).unwrap();
+    let x = heap.allocate_first_fit(large).unwrap();
+    let y = heap.allocate_first_fit(small).unwrap();
+    assert_eq!(y.as_ptr() as usize, base + 1504);
+    assert_eq!(heap.regions()[0].used(), 2504);
+    assert_eq!(
+        heap.try_remove_region(base + 2048, base + 4096),
+        Err(RemoveError::InUse { live: 456, first: base + 2048..base + 2504 })
+    );
+    unsafe {
+        heap.deallocate(x, large);
+        heap.deallocate(y, small);
+    }
+    assert!(heap.allocate_first_fit(Layout::from_size_align(4096, 8).unwrap()).is_ok());
+}
+
+#[test]
+fn region_fills_gap() {
+    let space = Box::leak(Box::new([0usize; 384]));
+    let base = space.as_ptr() as usize;
+    let mut heap = Heap::empty();
+    unsafe {
+        heap.add_region(base, 1024);
+        heap.add_region(base + 2048, 1024);
+        heap.add_region(base + 1024, 1024);
+    }
+    assert_eq!(heap.regions().len(), 1);
+    assert_eq!(heap.regions()[0].size(), 3072);
+    assert_eq!(heap.check_invariants(), Ok(()));
+    assert!(heap.allocate_first_fit(Layout::from_size_align(3072, 8).unwrap()).is_ok());
+    assert_eq!(heap.regions()[0].used(), 3072);
+}
+
+#[test]
+fn too_many_regions() {
+    let space = Box::leak(Box::new([0usize; 128 * (MAX_REGIONS + 1)]));
+    let base = space.as_ptr() as usize;
+    let mut heap = Heap::empty();
+    // 区域之间留出空隙,每块都占一个槽位
+    for index in 0..MAX_REGIONS {
+        unsafe { heap.add_region(base + index * 1024, 512) };
+    }
+    assert_eq!(heap.regions().len(), MAX_REGIONS);
+
+    let ninth = base + MAX_REGIONS * 1024;
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe {
+        heap.add_region(ninth, 512);
+    }));
+    assert!(result.is_err());
+
+    // 失败的加入不会留下没有区域记录的空洞
+    assert_eq!(heap.regions().len(), MAX_REGIONS);
+    assert_eq!(heap.size(), MAX_REGIONS * 512);
+    assert_eq!(heap.free(), MAX_REGIONS * 512);
+    assert_eq!(heap.check_invariants(), Ok(()));
+
+    // 与已有区域相邻的区域仍然可以加入
+    unsafe { heap.add_region(base + 512, 256) };
+    assert_eq!(heap.regions().len(), MAX_REGIONS);
+    assert_eq!(heap.regions()[0].size(), 768);
+    assert_eq!(heap.check_invariants(), Ok(()));
+}
+
+#[test]
+#[should_panic(expected = "overlaps")]
+fn overlapping_region() {
+    let space = Box::leak(Box::new([0usize; 256]));
+    let base = space.as_ptr() as usize;
+    let mut heap = unsafe { Heap::new(base, 1024) };
+    unsafe {
+        heap.add_region(base + 512, 1024);
+    }
+}
+
+#[test]
+fn remove_region() {
+    let space = Box::leak(Box::new([0usize; 128]));
+    let base = space.as_ptr() as usize;
+    let mut heap = unsafe { Heap::new(base, 1024) };
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let x = heap.allocate_first_fit(layout).unwrap();
+
+    assert_eq!(
+        heap.try_remove_region(base + 1024, base + 2048),
+        Err(RemoveError::NotInHeap { range: base + 1024..base + 2048 })
+    );
+    assert_eq!(
+        heap.try_remove_region(base, base + 128),
+        Err(RemoveError::InUse { live: 64, first: base..base + 64 })
+    );
+
+    // 从中间移除,区域被分成两块
+    assert_eq!(heap.try_remove_region(base + 256, base + 512), Ok(()));
+    assert_eq!(heap.size(), 768);
+    assert_eq!(heap.used(), 64);
+    let regions: Vec<_> = heap.regions().iter().map(|region| (region.start(), region.end())).collect();
+    assert_eq!(regions, [(base, base + 256), (base + 512, base + 1024)]);
+    let live: Vec<_> = heap.live_blocks(base, base + 1024).collect();
+    assert_eq!(live, [base..base + 64, base + 256..base + 512]);
+
+    // 移除整个区域
+    assert_eq!(heap.try_remove_region(base + 512, base + 1024), Ok(()));
+    assert_eq!(heap.regions().len(), 1);
+    unsafe { heap.deallocate(x, layout) };
+    assert_eq!(heap.free(), 256);
+    assert_eq!(heap.linkedlist.first_hole(), Some((base, 256)));
+}
+
+#[test]
+fn remove_region_keeps_order() {
+    let space = Box::leak(Box::new([0usize; 512]));
+    let base = space.as_ptr() as usize;
+    let mut heap = Heap::empty();
+    unsafe {
+        heap.add_region(base, 1024);
+        heap.add_region(base + 2048, 1024);
+    }
+
+    // 拆开第一块区域,剩下的两部分留在原来的位置
+    assert_eq!(heap.try_remove_region(base + 256, base + 512), Ok(()));
+    let regions: Vec<_> = heap.regions().iter().map(|region| (region.start(), region.end())).collect();
+    assert_eq!(
+        regions,
+        [(base, base + 256), (base + 512, base + 1024), (base + 2048, base + 3072)]
+    );
+
+    // extend仍然扩展最后加入的区域
+    unsafe { heap.extend(1024) };
+    assert_eq!(heap.regions().len(), 3);
+    assert_eq!(heap.regions()[2].end(), base + 4096);
+    assert_eq!(heap.check_invariants(), Ok(()));
+}
+
+#[test]
+fn fragmentation() {
+    let mut heap = new_heap();
+    let report = heap.fragmentation();
+    assert_eq!(report.free_block_count(), 1);
+    assert_eq!(report.free_blocks[9], 1);
+    assert_eq!(report.largest_free, 1000);
+
+    // 释放中间的块,留下两个空洞
+    let layout = Layout::from_size_align(200, align_of::<usize>()).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    let c = heap.allocate_first_fit(layout).unwrap();
+    unsafe {
+        heap.deallocate(b, layout);
+    }
+    let report = heap.fragmentation();
+    assert_eq!(report.free_block_count(), 2);
+    assert_eq!(report.free_blocks[7], 1);
+    assert_eq!(report.free_blocks[8], 1);
+    assert_eq!(report.free, 600);
+    assert_eq!(report.free, heap.free());
+    assert_eq!(report.largest_free, 400);
+    assert_eq!(report.internal_waste, 0);
+    assert_eq!(report.fragmentation_index(8), None);
+    assert_eq!(report.fragmentation_index(10), Some(1000 - (1000 + 585) / 2));
+
+    unsafe {
+        heap.deallocate(a, layout);
+        heap.deallocate(c, layout);
+    }
+    assert_eq!(heap.fragmentation().free_block_count(), 1);
+}
+
+#[test]
+fn check_invariants() {
+    let mut heap = new_heap();
+    assert_eq!(heap.check_invariants(), Ok(()));
+    let layout = Layout::from_size_align(200, align_of::<usize>()).unwrap();
+    let a = heap.allocate_first_fit(layout).unwrap();
+    let b = heap.allocate_first_fit(layout).unwrap();
+    let c = heap.allocate_first_fit(layout).unwrap();
+    unsafe {
+        heap.deallocate(b, layout);
+    }
+    assert_eq!(heap.check_invariants(), Ok(()));
+
+    // 直接改写b处空洞的大小来制造损坏
+    let hole = unsafe { &mut *(b.as_ptr() as *mut Hole) };
+    let b = b.as_ptr() as usize;
+    let tail = c.as_ptr() as usize + 200;
+    let top = bottom(&heap) + heap.size();
+    hole.size = 8;
+    assert_eq!(
+        heap.check_invariants(),
+        Err(InvariantError::HoleTooSmall { hole: b..b + 8, min_size: 16 })
+    );
+    hole.size = tail - b;
+    assert_eq!(
+        heap.check_invariants(),
+        Err(InvariantError::UncoalescedHoles { previous: b..tail, hole: tail..top })
+    );
+    hole.size = tail - b + 8;
+    assert_eq!(
+        heap.check_invariants(),
+        Err(InvariantError::HoleOverlap { previous: b..tail + 8, hole: tail..top })
+    );
+    hole.size = 192;
+    assert_eq!(
+        heap.check_invariants(),
+        Err(InvariantError::FreeMismatch { expected: 600, found: 592 })
+    );
+    hole.size = 200;
+
+    let tail_hole = unsafe { &mut *(tail as *mut Hole) };
+    tail_hole.size += 8;
+    assert_eq!(
+        heap.check_invariants(),
+        Err(InvariantError::HoleOutOfBounds { hole: tail..top + 8 })
+    );
+    tail_hole.size -= 8;
+    assert_eq!(heap.check_invariants(), Ok(()));
+
+    unsafe {
+        heap.deallocate(a, layout);
+        heap.deallocate(c, layout);
+    }
+    assert_eq!(heap.check_invariants(), Ok(()));
+
+    // 低地址的区域后加入,两侧的空洞合并之后仍然在区域之内
+    let space = Box::leak(Box::new([0usize; 512]));
+    let base = space.as_ptr() as usize;
+    let mut heap = Heap::empty();
+    unsafe {
+        heap.add_region(base + 2048, 2048);
+        heap.add_region(base, 2048);
+    }
+    assert_eq!(heap.check_invariants(), Ok(()));
+}
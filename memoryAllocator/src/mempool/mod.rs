@@ -0,0 +1,111 @@
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use crate::error::AllocError;
+use crate::raw_heap::RawHeap;
+
+#[cfg(all(test, feature = "buddy"))]
+mod test;
+
+/// A reserve of `N` pre-allocated elements of one `Layout`, like `mempool_t` in Linux, for
+/// paths that must make progress while memory is being freed, e.g. block I/O.
+///
+/// An allocation is served by the heap first and only takes an element from the reserve when
+/// the heap fails. A freed element refills the reserve before anything goes back to the heap.
+/// As long as every element is eventually freed, a caller that keeps at most `N` elements in
+/// flight can always allocate.
+///
+/// The pool does not own the heap: every call takes the heap it was created from, so that the
+/// heap can sit behind a lock shared with other users. Dropping the pool leaks the reserve;
+/// use [`MemPool::destroy`] to give it back.
+pub struct MemPool<const N: usize> {
+    layout: Layout,
+    reserve: [usize; N],
+    count: usize,
+}
+
+impl<const N: usize> MemPool<N> {
+    /// Creates a pool for elements of `layout` and fills its reserve from `heap`. If the heap
+    /// cannot provide all `N` elements, the ones already taken are freed and the error of the
+    /// failed allocation is returned.
+    pub fn new<H: RawHeap>(heap: &mut H, layout: Layout) -> Result<Self, AllocError> {
+        let mut pool = MemPool {
+            layout,
+            reserve: [0; N],
+            count: 0,
+        };
+        while pool.count < N {
+            match heap.alloc(layout) {
+                Ok(element) => {
+                    pool.reserve[pool.count] = element.as_ptr() as usize;
+                    pool.count += 1;
+                }
+                Err(err) => {
+                    pool.destroy(heap);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(pool)
+    }
+
+    /// Allocates an element, from `heap` if possible and from the reserve otherwise. Returns
+    /// the error of the heap if the reserve is empty too.
+    pub fn alloc<H: RawHeap>(&mut self, heap: &mut H) -> Result<NonNull<u8>, AllocError> {
+        match heap.alloc(self.layout) {
+            Ok(element) => Ok(element),
+            Err(err) => {
+                if self.count == 0 {
+                    return Err(err);
+                }
+                self.count -= 1;
+                Ok(unsafe { NonNull::new_unchecked(self.reserve[self.count] as *mut u8) })
+            }
+        }
+    }
+
+    /// Frees an element. It refills the reserve if the reserve is short, and goes back to
+    /// `heap` otherwise.
+    ///
+    /// # Safety
+    ///
+    /// `element` must have been returned by [`MemPool::alloc`] on this pool with the same
+    /// `heap`, and must not have been freed since.
+    pub unsafe fn dealloc<H: RawHeap>(&mut self, heap: &mut H, element: NonNull<u8>) {
+        if self.count < N {
+            self.reserve[self.count] = element.as_ptr() as usize;
+            self.count += 1;
+        } else {
+            heap.dealloc(element, self.layout);
+        }
+    }
+
+    /// Gives the reserve back to `heap`. Elements still in use must be freed to the heap
+    /// directly, with the layout of the pool.
+    pub fn destroy<H: RawHeap>(mut self, heap: &mut H) {
+        while self.count > 0 {
+            self.count -= 1;
+            unsafe {
+                heap.dealloc(
+                    NonNull::new_unchecked(self.reserve[self.count] as *mut u8),
+                    self.layout,
+                );
+            }
+        }
+    }
+
+    /// Returns the layout of the elements.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Returns the number of elements in the reserve.
+    pub fn reserved(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the size the reserve is kept at.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+}
@@ -0,0 +1,99 @@
+use super::MemPool;
+use crate::buddy_allocator::Heap;
+use crate::raw_heap::{add_test_region, RawHeap};
+use core::alloc::Layout;
+use std::prelude::v1::*;
+
+fn layout() -> Layout {
+    Layout::from_size_align(64, 8).unwrap()
+}
+
+/// Allocates from `heap` until it fails.
+fn exhaust(heap: &mut Heap<32>) -> Vec<core::ptr::NonNull<u8>> {
+    let mut elements = Vec::new();
+    while let Ok(element) = heap.alloc(layout()) {
+        elements.push(element);
+    }
+    elements
+}
+
+#[test]
+fn test_alloc_prefers_heap() {
+    let mut heap = Heap::<32>::new();
+    add_test_region(&mut heap, 512);
+    let mut pool = MemPool::<4>::new(&mut heap, layout()).unwrap();
+    assert_eq!(pool.reserved(), 4);
+    assert_eq!(pool.capacity(), 4);
+
+    // 堆还有内存时不动保留区
+    let element = pool.alloc(&mut heap).unwrap();
+    assert_eq!(pool.reserved(), 4);
+    assert_eq!(heap.stats().user, 5 * 64);
+    unsafe { pool.dealloc(&mut heap, element) };
+    assert_eq!(heap.stats().user, 4 * 64);
+    pool.destroy(&mut heap);
+    assert_eq!(heap.stats().user, 0);
+}
+
+#[test]
+fn test_reserve_fallback() {
+    let mut heap = Heap::<32>::new();
+    add_test_region(&mut heap, 512);
+    let mut pool = MemPool::<4>::new(&mut heap, layout()).unwrap();
+    let elements = exhaust(&mut heap);
+
+    // 堆耗尽之后从保留区分配,最多N个
+    let mut reserved = Vec::new();
+    for left in (0..4).rev() {
+        reserved.push(pool.alloc(&mut heap).unwrap());
+        assert_eq!(pool.reserved(), left);
+    }
+    let err = pool.alloc(&mut heap).unwrap_err();
+    assert_eq!(Err(err), heap.alloc(layout()));
+
+    unsafe {
+        for element in reserved.into_iter().chain(elements) {
+            pool.dealloc(&mut heap, element);
+        }
+    }
+    pool.destroy(&mut heap);
+    assert_eq!(heap.stats().user, 0);
+}
+
+#[test]
+fn test_dealloc_refills_reserve() {
+    let mut heap = Heap::<32>::new();
+    add_test_region(&mut heap, 512);
+    let mut pool = MemPool::<4>::new(&mut heap, layout()).unwrap();
+    let mut elements = exhaust(&mut heap);
+    let taken: Vec<_> = (0..3).map(|_| pool.alloc(&mut heap).unwrap()).collect();
+    assert_eq!(pool.reserved(), 1);
+
+    // 释放的元素先补满保留区,之后才还给堆
+    let allocated = heap.stats().allocated;
+    unsafe {
+        for element in taken {
+            pool.dealloc(&mut heap, element);
+        }
+        assert_eq!(pool.reserved(), 4);
+        assert_eq!(heap.stats().allocated, allocated);
+        pool.dealloc(&mut heap, elements.pop().unwrap());
+    }
+    assert_eq!(pool.reserved(), 4);
+    assert_eq!(heap.stats().allocated, allocated - 64);
+
+    for element in elements {
+        heap.dealloc(element, layout());
+    }
+    pool.destroy(&mut heap);
+    assert_eq!(heap.stats().user, 0);
+}
+
+#[test]
+fn test_new_fails() {
+    let mut heap = Heap::<32>::new();
+    add_test_region(&mut heap, 64);
+    // 堆里只有8个元素,装不满16个的保留区
+    assert!(MemPool::<16>::new(&mut heap, layout()).is_err());
+    assert_eq!(heap.stats().allocated, 0);
+}
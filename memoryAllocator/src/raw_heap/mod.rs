@@ -0,0 +1,101 @@
+#[cfg(feature = "use_spin")]
+use core::alloc::GlobalAlloc;
+use core::alloc::Layout;
+#[cfg(feature = "use_spin")]
+use core::ops::Deref;
+use core::ptr::NonNull;
+#[cfg(feature = "use_spin")]
+use spin::Mutex;
+
+use crate::error::AllocError;
+
+// 测试都需要一种具体的堆
+#[cfg(all(test, any(feature = "buddy", feature = "linked_list")))]
+mod test;
+
+/// Adds a leaked region of `words` words to `heap`. Returns the start and size of the region.
+#[cfg(all(test, any(feature = "buddy", feature = "linked_list")))]
+pub(crate) fn add_test_region<H: RawHeap>(heap: &mut H, words: usize) -> (usize, usize) {
+    let space = vec![0usize; words].leak();
+    let (start, size) = (space.as_ptr() as usize, core::mem::size_of_val(space));
+    unsafe {
+        heap.add_region(start, size);
+    }
+    (start, size)
+}
+
+/// Byte counters shared by every heap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Bytes handed to the heap through `add_region`.
+    pub total: usize,
+    /// Bytes requested by callers.
+    pub user: usize,
+    /// Bytes actually taken from the heap, including rounding.
+    pub allocated: usize,
+}
+
+impl HeapStats {
+    /// Returns the number of bytes that are not allocated.
+    pub fn free(&self) -> usize {
+        self.total - self.allocated
+    }
+}
+
+/// The operations every allocator family in this crate provides, so that wrappers such as
+/// [`Locked`] and test harnesses can be written once.
+pub trait RawHeap {
+    /// Allocates a block for `layout`.
+    fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError>;
+
+    /// Frees a block previously returned by `alloc`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `alloc` on this heap with the same `layout`, and must
+    /// not have been freed since.
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout);
+
+    /// Hands `size` bytes starting at `start` to the heap.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be valid and must not be used for anything else.
+    unsafe fn add_region(&mut self, start: usize, size: usize);
+
+    /// Returns the current byte counters.
+    fn stats(&self) -> HeapStats;
+}
+
+/// A spinlock around any [`RawHeap`], usable as `#[global_allocator]`.
+#[cfg(feature = "use_spin")]
+pub struct Locked<H: RawHeap>(Mutex<H>);
+
+#[cfg(feature = "use_spin")]
+impl<H: RawHeap> Locked<H> {
+    pub const fn new(heap: H) -> Self {
+        Locked(Mutex::new(heap))
+    }
+}
+
+#[cfg(feature = "use_spin")]
+impl<H: RawHeap> Deref for Locked<H> {
+    type Target = Mutex<H>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(feature = "use_spin")]
+unsafe impl<H: RawHeap> GlobalAlloc for Locked<H> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        RawHeap::alloc(&mut *self.0.lock(), layout)
+            .ok()
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        RawHeap::dealloc(&mut *self.0.lock(), NonNull::new_unchecked(ptr), layout)
+    }
+}
@@ -0,0 +1,66 @@
+use super::{add_test_region, RawHeap};
+use core::alloc::Layout;
+use std::prelude::v1::*;
+
+/// Runs the same allocation pattern against any heap.
+fn exercise<H: RawHeap>(mut heap: H) {
+    let (_, size) = add_test_region(&mut heap, 16 * 1024);
+    assert_eq!(heap.stats().total, size);
+    assert_eq!(heap.stats().user, 0);
+
+    let mut allocations = Vec::new();
+    for size in [1, 16, 100, 1000, 4000] {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        let ptr = heap.alloc(layout).unwrap();
+        assert_eq!(ptr.as_ptr() as usize % layout.align(), 0);
+        allocations.push((ptr, layout));
+    }
+    assert!(heap.stats().user >= 1 + 16 + 100 + 1000 + 4000);
+    assert!(heap.stats().allocated >= heap.stats().user);
+
+    for (ptr, layout) in allocations {
+        unsafe { heap.dealloc(ptr, layout) };
+    }
+    assert_eq!(heap.stats().user, 0);
+}
+
+#[cfg(feature = "buddy")]
+#[test]
+fn test_buddy_heap() {
+    exercise(crate::buddy_allocator::Heap::<32>::new());
+}
+
+#[cfg(feature = "linked_list")]
+#[test]
+fn test_linked_list_heap() {
+    exercise(crate::linked_list_allocator::Heap::empty());
+}
+
+#[cfg(feature = "slab")]
+#[test]
+fn test_slab_heap() {
+    exercise(crate::slab_allocator::SlabHeap::<32>::new(64));
+}
+
+#[cfg(feature = "slab")]
+#[test]
+fn test_kmalloc() {
+    exercise(crate::slab_allocator::Kmalloc::<32>::new());
+}
+
+#[cfg(all(feature = "buddy", feature = "use_spin"))]
+#[test]
+fn test_locked() {
+    use super::Locked;
+    use core::alloc::GlobalAlloc;
+
+    let heap = Locked::new(crate::buddy_allocator::Heap::<32>::new());
+    add_test_region(&mut *heap.lock(), 1024);
+    unsafe {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let ptr = heap.alloc(layout);
+        assert!(!ptr.is_null());
+        heap.dealloc(ptr, layout);
+    }
+    assert_eq!(heap.lock().stats().allocated, 0);
+}
@@ -0,0 +1,213 @@
+#[cfg(feature = "use_spin")]
+use core::alloc::GlobalAlloc;
+use core::alloc::Layout;
+use core::cmp::max;
+use core::fmt;
+#[cfg(feature = "use_spin")]
+use core::ops::Deref;
+use core::ptr::NonNull;
+#[cfg(feature = "use_spin")]
+use spin::Mutex;
+
+use super::SlabCache;
+use crate::buddy_allocator::Heap;
+use crate::error::AllocError;
+use crate::fragmentation::FragmentationReport;
+use crate::raw_heap::{HeapStats, RawHeap};
+
+/// Object sizes of the slab caches behind `Kmalloc`.
+pub const KMALLOC_SIZES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// A kmalloc-style front end. Small layouts are served by one slab cache per size class,
+/// anything above the cutover threshold goes straight to the buddy `Heap`.
+pub struct Kmalloc<const ORDER: usize> {
+    caches: [SlabCache; KMALLOC_SIZES.len()],
+    threshold: usize,
+    heap: Heap<ORDER>,
+}
+
+impl<const ORDER: usize> Kmalloc<ORDER> {
+    /// Creates an empty front end that uses every size class.
+    pub const fn new() -> Self {
+        Self::with_threshold(KMALLOC_SIZES[KMALLOC_SIZES.len() - 1])
+    }
+
+    /// Creates an empty front end that only serves layouts of at most `threshold` bytes from
+    /// the slab caches. The threshold is fixed for the lifetime of the allocator, since
+    /// `dealloc` relies on it to route a pointer back to where it came from.
+    pub const fn with_threshold(threshold: usize) -> Self {
+        Kmalloc {
+            caches: [
+                SlabCache::new(KMALLOC_SIZES[0]),
+                SlabCache::new(KMALLOC_SIZES[1]),
+                SlabCache::new(KMALLOC_SIZES[2]),
+                SlabCache::new(KMALLOC_SIZES[3]),
+                SlabCache::new(KMALLOC_SIZES[4]),
+                SlabCache::new(KMALLOC_SIZES[5]),
+                SlabCache::new(KMALLOC_SIZES[6]),
+                SlabCache::new(KMALLOC_SIZES[7]),
+                SlabCache::new(KMALLOC_SIZES[8]),
+            ],
+            threshold,
+            heap: Heap::<ORDER>::new(),
+        }
+    }
+
+    /// Adds the memory in `[start, end)` to the backing heap.
+    ///
+    /// # Safety
+    ///
+    /// The range must be valid, unused memory, as for `Heap::free_heap`.
+    pub unsafe fn free_heap(&mut self, start: usize, end: usize) {
+        self.heap.free_heap(start, end);
+    }
+
+    /// Adds `size` bytes starting at `start` to the backing heap.
+    ///
+    /// # Safety
+    ///
+    /// The range must be valid, unused memory, as for `Heap::init`.
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        self.heap.init(start, size);
+    }
+
+    /// Returns the index of the size class serving `layout`, or `None` if it goes to the heap.
+    pub fn class_of(&self, layout: &Layout) -> Option<usize> {
+        let size = max(layout.size(), layout.align());
+        if size > self.threshold {
+            return None;
+        }
+        KMALLOC_SIZES
+            .iter()
+            .position(|&class| class >= size && class <= self.threshold)
+    }
+
+    pub fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        match self.class_of(&layout) {
+            Some(class) => self.caches[class]
+                .alloc(&mut self.heap)
+                .map_err(|err| err.with_layout(layout)),
+            None => self.heap.alloc(layout),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`Kmalloc::alloc`] with the same `layout`.
+    pub unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        match self.class_of(&layout) {
+            Some(class) => self.caches[class].dealloc(ptr),
+            None => self.heap.dealloc(ptr, layout),
+        }
+    }
+
+    /// Returns the empty slabs of every cache to the backing heap.
+    pub fn shrink(&mut self) -> usize {
+        let heap = &mut self.heap;
+        self.caches.iter_mut().map(|cache| cache.shrink(heap)).sum()
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn caches(&self) -> &[SlabCache] {
+        &self.caches
+    }
+
+    pub fn heap(&self) -> &Heap<ORDER> {
+        &self.heap
+    }
+
+    /// Returns the free blocks of the backing heap. The internal waste also counts the free
+    /// objects and the slack of the slabs of every size class.
+    pub fn fragmentation(&self) -> FragmentationReport {
+        let stats = RawHeap::stats(self);
+        let mut report = self.heap.fragmentation();
+        report.internal_waste = stats.allocated - stats.user;
+        report
+    }
+}
+
+impl<const ORDER: usize> Default for Kmalloc<ORDER> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const ORDER: usize> RawHeap for Kmalloc<ORDER> {
+    fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        Kmalloc::alloc(self, layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        Kmalloc::dealloc(self, ptr, layout)
+    }
+
+    unsafe fn add_region(&mut self, start: usize, size: usize) {
+        self.init(start, size)
+    }
+
+    fn stats(&self) -> HeapStats {
+        let mut stats = RawHeap::stats(&self.heap);
+        for cache in self.caches.iter() {
+            stats.user = stats.user - cache.stats_slab_bytes() + cache.stats_object_bytes();
+        }
+        stats
+    }
+}
+
+impl<const ORDER: usize> fmt::Debug for Kmalloc<ORDER> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Kmalloc")
+            .field("threshold", &self.threshold)
+            .field("caches", &self.caches)
+            .field("heap", &self.heap)
+            .finish()
+    }
+}
+
+#[cfg(feature = "use_spin")]
+pub struct LockedKmalloc<const ORDER: usize>(Mutex<Kmalloc<ORDER>>);
+
+#[cfg(feature = "use_spin")]
+impl<const ORDER: usize> LockedKmalloc<ORDER> {
+    pub const fn new() -> Self {
+        LockedKmalloc(Mutex::new(Kmalloc::<ORDER>::new()))
+    }
+
+    pub const fn with_threshold(threshold: usize) -> Self {
+        LockedKmalloc(Mutex::new(Kmalloc::<ORDER>::with_threshold(threshold)))
+    }
+}
+
+#[cfg(feature = "use_spin")]
+impl<const ORDER: usize> Default for LockedKmalloc<ORDER> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "use_spin")]
+impl<const ORDER: usize> Deref for LockedKmalloc<ORDER> {
+    type Target = Mutex<Kmalloc<ORDER>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(feature = "use_spin")]
+unsafe impl<const ORDER: usize> GlobalAlloc for LockedKmalloc<ORDER> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0
+            .lock()
+            .alloc(layout)
+            .ok()
+            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+}
@@ -0,0 +1,434 @@
+#[cfg(feature = "use_spin")]
+use core::alloc::GlobalAlloc;
+use core::alloc::Layout;
+use core::fmt;
+use core::mem::size_of;
+#[cfg(feature = "use_spin")]
+use core::ops::Deref;
+use core::ptr::{self, NonNull};
+#[cfg(feature = "use_spin")]
+use spin::Mutex;
+
+use crate::buddy_allocator::linked_list::LinkedList;
+use crate::buddy_allocator::Heap;
+use crate::error::AllocError;
+use crate::fragmentation::FragmentationReport;
+use crate::raw_heap::{HeapStats, RawHeap};
+
+#[cfg(test)]
+mod test;
+mod kmalloc;
+
+pub use kmalloc::*;
+
+/// Smallest slab that is requested from the backing heap.
+pub const MIN_SLAB_SIZE: usize = 4096;
+
+/// Slabs are sized so that at least this many objects fit next to the header.
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+/// Header stored at the start of every slab. The slab itself is aligned to its own size, so
+/// the header of any object can be found by aligning the object address down.
+struct Slab {
+    prev: *mut Slab,
+    next: *mut Slab,
+    free: LinkedList,
+    in_use: usize,
+}
+
+/// An intrusive doubly linked list of slabs, threaded through the slab headers.
+struct SlabList {
+    head: *mut Slab,
+    len: usize,
+}
+
+impl SlabList {
+    const fn new() -> Self {
+        SlabList {
+            head: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    unsafe fn push(&mut self, slab: *mut Slab) {
+        (*slab).prev = ptr::null_mut();
+        (*slab).next = self.head;
+        if !self.head.is_null() {
+            (*self.head).prev = slab;
+        }
+        self.head = slab;
+        self.len += 1;
+    }
+
+    /// Unlinks `slab`, which must currently be in this list.
+    unsafe fn remove(&mut self, slab: *mut Slab) {
+        if (*slab).prev.is_null() {
+            self.head = (*slab).next;
+        } else {
+            (*(*slab).prev).next = (*slab).next;
+        }
+        if !(*slab).next.is_null() {
+            (*(*slab).next).prev = (*slab).prev;
+        }
+        (*slab).prev = ptr::null_mut();
+        (*slab).next = ptr::null_mut();
+        self.len -= 1;
+    }
+
+    fn pop(&mut self) -> Option<*mut Slab> {
+        if self.is_empty() {
+            return None;
+        }
+        let slab = self.head;
+        unsafe { self.remove(slab) };
+        Some(slab)
+    }
+}
+
+/// A cache of equal-sized objects carved out of slabs taken from a buddy `Heap`.
+///
+/// Slabs are kept on one of three lists: `full` (no free object), `partial` and `empty`
+/// (no object in use). Allocation prefers partial slabs so that empty ones can be returned
+/// to the heap with [`SlabCache::shrink`].
+pub struct SlabCache {
+    object_size: usize,
+    object_align: usize,
+    slab_size: usize,
+    // 第一个对象在slab中的偏移,前面是slab头
+    first_offset: usize,
+    objects_per_slab: usize,
+
+    full: SlabList,
+    partial: SlabList,
+    empty: SlabList,
+
+    in_use: usize,
+}
+
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    /// Creates an empty cache for objects of `object_size` bytes. The size is rounded up to a
+    /// multiple of `usize`, since free objects store the free-list link in place.
+    pub const fn new(object_size: usize) -> Self {
+        let unit = size_of::<usize>();
+        let object_size = if object_size < unit {
+            unit
+        } else {
+            (object_size + unit - 1) & !(unit - 1)
+        };
+        // 对象按照其大小中最大的2的幂对齐
+        let object_align = object_size & object_size.wrapping_neg();
+        let header = size_of::<Slab>();
+        let first_offset = (header + object_align - 1) & !(object_align - 1);
+
+        let wanted = (first_offset + object_size * MIN_OBJECTS_PER_SLAB).next_power_of_two();
+        let slab_size = if wanted < MIN_SLAB_SIZE {
+            MIN_SLAB_SIZE
+        } else {
+            wanted
+        };
+
+        SlabCache {
+            object_size,
+            object_align,
+            slab_size,
+            first_offset,
+            objects_per_slab: (slab_size - first_offset) / object_size,
+            full: SlabList::new(),
+            partial: SlabList::new(),
+            empty: SlabList::new(),
+            in_use: 0,
+        }
+    }
+
+    /// Returns the size of the objects handed out by this cache.
+    pub fn object_size(&self) -> usize {
+        self.object_size
+    }
+
+    /// Returns the size of a single slab requested from the backing heap.
+    pub fn slab_size(&self) -> usize {
+        self.slab_size
+    }
+
+    /// Returns the number of objects stored in each slab.
+    pub fn objects_per_slab(&self) -> usize {
+        self.objects_per_slab
+    }
+
+    /// Returns true if an allocation with the given layout can be served by this cache.
+    pub fn fits(&self, layout: &Layout) -> bool {
+        layout.size() <= self.object_size && layout.align() <= self.object_align
+    }
+
+    /// Returns the number of objects currently handed out.
+    pub fn stats_in_use(&self) -> usize {
+        self.in_use
+    }
+
+    /// Returns the number of bytes requested by callers of this cache.
+    pub fn stats_object_bytes(&self) -> usize {
+        self.in_use * self.object_size
+    }
+
+    /// Returns the number of bytes taken from the heap for slabs.
+    pub fn stats_slab_bytes(&self) -> usize {
+        (self.full.len + self.partial.len + self.empty.len) * self.slab_size
+    }
+
+    /// Returns the number of slabs on the full, partial and empty lists.
+    pub fn stats_slabs(&self) -> (usize, usize, usize) {
+        (self.full.len, self.partial.len, self.empty.len)
+    }
+
+    /// Allocates one object, growing the cache with a new slab from `heap` if no slab has a
+    /// free object left.
+    pub fn alloc<const ORDER: usize>(&mut self, heap: &mut Heap<ORDER>) -> Result<NonNull<u8>, AllocError> {
+        let slab = match self.partial.pop() {
+            Some(slab) => slab,
+            None => match self.empty.pop() {
+                Some(slab) => slab,
+                None => self.grow(heap)?,
+            },
+        };
+
+        unsafe {
+            let object = (*slab)
+                .free
+                .pop()
+                .expect("slab on the partial or empty list should have a free object");
+            (*slab).in_use += 1;
+            if (*slab).in_use == self.objects_per_slab {
+                self.full.push(slab);
+            } else {
+                self.partial.push(slab);
+            }
+            self.in_use += 1;
+            Ok(NonNull::new_unchecked(object as *mut u8))
+        }
+    }
+
+    /// Returns an object to the slab it was carved from.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`SlabCache::alloc`] on this cache and must not have
+    /// been freed since.
+    pub unsafe fn dealloc(&mut self, ptr: NonNull<u8>) {
+        let addr = ptr.as_ptr() as usize;
+        let slab = (addr & !(self.slab_size - 1)) as *mut Slab;
+        debug_assert!(
+            addr - slab as usize >= self.first_offset
+                && (addr - slab as usize - self.first_offset).is_multiple_of(self.object_size),
+            "invalid slab deallocation"
+        );
+
+        let was_full = (*slab).in_use == self.objects_per_slab;
+        (*slab).free.push(addr as *mut usize);
+        (*slab).in_use -= 1;
+        self.in_use -= 1;
+
+        if was_full {
+            self.full.remove(slab);
+            self.partial.push(slab);
+        }
+        if (*slab).in_use == 0 {
+            self.partial.remove(slab);
+            self.empty.push(slab);
+        }
+    }
+
+    /// Returns every empty slab to `heap`, and the number of slabs released.
+    pub fn shrink<const ORDER: usize>(&mut self, heap: &mut Heap<ORDER>) -> usize {
+        let mut count = 0;
+        while let Some(slab) = self.empty.pop() {
+            heap.dealloc(unsafe { NonNull::new_unchecked(slab as *mut u8) }, self.slab_layout());
+            count += 1;
+        }
+        count
+    }
+
+    fn slab_layout(&self) -> Layout {
+        Layout::from_size_align(self.slab_size, self.slab_size).unwrap()
+    }
+
+    /// Takes a new slab from `heap` and threads all of its objects onto the slab's free list.
+    fn grow<const ORDER: usize>(&mut self, heap: &mut Heap<ORDER>) -> Result<*mut Slab, AllocError> {
+        let base = heap.alloc(self.slab_layout())?.as_ptr() as usize;
+        let slab = base as *mut Slab;
+        unsafe {
+            slab.write(Slab {
+                prev: ptr::null_mut(),
+                next: ptr::null_mut(),
+                free: LinkedList::new(),
+                in_use: 0,
+            });
+            // 逆序压栈,使得低地址的对象先被分配
+            for i in (0..self.objects_per_slab).rev() {
+                let object = base + self.first_offset + i * self.object_size;
+                (*slab).free.push(object as *mut usize);
+            }
+        }
+        Ok(slab)
+    }
+}
+
+impl fmt::Debug for SlabCache {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("SlabCache")
+            .field("object_size", &self.object_size)
+            .field("slab_size", &self.slab_size)
+            .field("in_use", &self.in_use)
+            .field("full", &self.full.len)
+            .field("partial", &self.partial.len)
+            .field("empty", &self.empty.len)
+            .finish()
+    }
+}
+
+/// A single `SlabCache` together with the buddy `Heap` it takes its slabs from. Layouts that
+/// do not fit into the cache are served by the heap directly.
+pub struct SlabHeap<const ORDER: usize> {
+    cache: SlabCache,
+    heap: Heap<ORDER>,
+}
+
+impl<const ORDER: usize> SlabHeap<ORDER> {
+    /// Creates an empty slab heap for objects of `object_size` bytes.
+    pub const fn new(object_size: usize) -> Self {
+        SlabHeap {
+            cache: SlabCache::new(object_size),
+            heap: Heap::<ORDER>::new(),
+        }
+    }
+
+    /// Adds the memory in `[start, end)` to the backing heap.
+    ///
+    /// # Safety
+    ///
+    /// The range must be valid, unused memory, as for `Heap::free_heap`.
+    pub unsafe fn free_heap(&mut self, start: usize, end: usize) {
+        self.heap.free_heap(start, end);
+    }
+
+    /// Adds `size` bytes starting at `start` to the backing heap.
+    ///
+    /// # Safety
+    ///
+    /// The range must be valid, unused memory, as for `Heap::init`.
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        self.heap.init(start, size);
+    }
+
+    pub fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if self.cache.fits(&layout) {
+            self.cache
+                .alloc(&mut self.heap)
+                .map_err(|err| err.with_layout(layout))
+        } else {
+            self.heap.alloc(layout)
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`SlabHeap::alloc`] with the same `layout`.
+    pub unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        if self.cache.fits(&layout) {
+            self.cache.dealloc(ptr);
+        } else {
+            self.heap.dealloc(ptr, layout);
+        }
+    }
+
+    /// Returns the empty slabs of the cache to the backing heap.
+    pub fn shrink(&mut self) -> usize {
+        self.cache.shrink(&mut self.heap)
+    }
+
+    pub fn cache(&self) -> &SlabCache {
+        &self.cache
+    }
+
+    pub fn heap(&self) -> &Heap<ORDER> {
+        &self.heap
+    }
+
+    /// Returns the free blocks of the backing heap. The internal waste also counts the free
+    /// objects and the slack of the slabs.
+    pub fn fragmentation(&self) -> FragmentationReport {
+        let stats = RawHeap::stats(self);
+        let mut report = self.heap.fragmentation();
+        report.internal_waste = stats.allocated - stats.user;
+        report
+    }
+}
+
+impl<const ORDER: usize> RawHeap for SlabHeap<ORDER> {
+    fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        SlabHeap::alloc(self, layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        SlabHeap::dealloc(self, ptr, layout)
+    }
+
+    unsafe fn add_region(&mut self, start: usize, size: usize) {
+        self.init(start, size)
+    }
+
+    fn stats(&self) -> HeapStats {
+        let mut stats = RawHeap::stats(&self.heap);
+        stats.user = stats.user - self.cache.stats_slab_bytes() + self.cache.stats_object_bytes();
+        stats
+    }
+}
+
+impl<const ORDER: usize> fmt::Debug for SlabHeap<ORDER> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("SlabHeap")
+            .field("cache", &self.cache)
+            .field("heap", &self.heap)
+            .finish()
+    }
+}
+
+#[cfg(feature = "use_spin")]
+pub struct LockedSlabHeap<const ORDER: usize>(Mutex<SlabHeap<ORDER>>);
+
+#[cfg(feature = "use_spin")]
+impl<const ORDER: usize> LockedSlabHeap<ORDER> {
+    /// Creates an empty slab heap for objects of `object_size` bytes.
+    pub const fn new(object_size: usize) -> Self {
+        LockedSlabHeap(Mutex::new(SlabHeap::<ORDER>::new(object_size)))
+    }
+}
+
+#[cfg(feature = "use_spin")]
+impl<const ORDER: usize> Deref for LockedSlabHeap<ORDER> {
+    type Target = Mutex<SlabHeap<ORDER>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[cfg(feature = "use_spin")]
+unsafe impl<const ORDER: usize> GlobalAlloc for LockedSlabHeap<ORDER> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0
+            .lock()
+            .alloc(layout)
+            .ok()
+            .map_or(ptr::null_mut(), |allocation| allocation.as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+}
@@ -0,0 +1,180 @@
+use super::SlabCache;
+use super::SlabHeap;
+use super::Kmalloc;
+use crate::buddy_allocator::Heap;
+use core::alloc::Layout;
+use core::mem::{size_of, size_of_val};
+use std::prelude::v1::*;
+
+fn new_heap() -> Heap<32> {
+    let mut heap = Heap::<32>::new();
+    let space = Box::leak(vec![0usize; 16 * 1024].into_boxed_slice());
+    unsafe {
+        let start = space.as_ptr() as usize;
+        heap.free_heap(start, start + size_of_val(space));
+    }
+    heap
+}
+
+#[test]
+fn test_cache_geometry() {
+    let cache = SlabCache::new(1);
+    assert_eq!(cache.object_size(), size_of::<usize>());
+    assert_eq!(cache.slab_size(), super::MIN_SLAB_SIZE);
+
+    let cache = SlabCache::new(2048);
+    assert!(cache.objects_per_slab() >= 7);
+    assert!(cache.fits(&Layout::from_size_align(2048, 2048).unwrap()));
+    assert!(!cache.fits(&Layout::from_size_align(2049, 8).unwrap()));
+}
+
+#[test]
+fn test_cache_empty_heap() {
+    let mut heap = Heap::<32>::new();
+    let mut cache = SlabCache::new(64);
+    assert!(cache.alloc(&mut heap).is_err());
+}
+
+#[test]
+fn test_cache_alloc_and_free() {
+    let mut heap = new_heap();
+    let mut cache = SlabCache::new(64);
+
+    let a = cache.alloc(&mut heap).unwrap();
+    let b = cache.alloc(&mut heap).unwrap();
+    assert_ne!(a, b);
+    assert_eq!(a.as_ptr() as usize % 64, 0);
+    assert_eq!(b.as_ptr() as usize - a.as_ptr() as usize, 64);
+    assert_eq!(cache.stats_in_use(), 2);
+    assert_eq!(cache.stats_slabs(), (0, 1, 0));
+
+    unsafe {
+        cache.dealloc(a);
+        cache.dealloc(b);
+    }
+    assert_eq!(cache.stats_in_use(), 0);
+    assert_eq!(cache.stats_slabs(), (0, 0, 1));
+
+    // 空的slab会被重新使用
+    let c = cache.alloc(&mut heap).unwrap();
+    assert_eq!(cache.stats_slabs(), (0, 1, 0));
+    unsafe { cache.dealloc(c) };
+}
+
+#[test]
+fn test_cache_full_partial_empty() {
+    let mut heap = new_heap();
+    let mut cache = SlabCache::new(512);
+    let per_slab = cache.objects_per_slab();
+
+    let mut objects = Vec::new();
+    for _ in 0..per_slab + 1 {
+        objects.push(cache.alloc(&mut heap).unwrap());
+    }
+    assert_eq!(cache.stats_slabs(), (1, 1, 0));
+
+    // 释放第一个slab中的一个对象,该slab从full移到partial
+    unsafe { cache.dealloc(objects.remove(0)) };
+    assert_eq!(cache.stats_slabs(), (0, 2, 0));
+
+    for object in objects.drain(..) {
+        unsafe { cache.dealloc(object) };
+    }
+    assert_eq!(cache.stats_slabs(), (0, 0, 2));
+
+    let allocated = heap.stats_alloc_actual();
+    assert_eq!(cache.shrink(&mut heap), 2);
+    assert_eq!(cache.stats_slabs(), (0, 0, 0));
+    assert_eq!(heap.stats_alloc_actual(), allocated - 2 * cache.slab_size());
+}
+
+#[test]
+fn test_slab_heap_routes_large_layouts() {
+    let mut slab = SlabHeap::<32>::new(32);
+    let space = Box::leak(vec![0usize; 16 * 1024].into_boxed_slice());
+    unsafe {
+        let start = space.as_ptr() as usize;
+        slab.free_heap(start, start + size_of_val(space));
+    }
+
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let large = Layout::from_size_align(1024, 8).unwrap();
+    let a = slab.alloc(small).unwrap();
+    let b = slab.alloc(large).unwrap();
+    assert_eq!(slab.cache().stats_in_use(), 1);
+    assert_eq!(slab.heap().stats_alloc_user(), slab.cache().slab_size() + 1024);
+
+    unsafe {
+        slab.dealloc(a, small);
+        slab.dealloc(b, large);
+    }
+    assert_eq!(slab.cache().stats_in_use(), 0);
+    assert_eq!(slab.shrink(), 1);
+    assert_eq!(slab.heap().stats_alloc_actual(), 0);
+}
+
+#[test]
+fn test_kmalloc_size_classes() {
+    let kmalloc = Kmalloc::<32>::new();
+    assert_eq!(kmalloc.class_of(&Layout::from_size_align(1, 1).unwrap()), Some(0));
+    assert_eq!(kmalloc.class_of(&Layout::from_size_align(9, 1).unwrap()), Some(1));
+    assert_eq!(kmalloc.class_of(&Layout::from_size_align(8, 64).unwrap()), Some(3));
+    assert_eq!(kmalloc.class_of(&Layout::from_size_align(2048, 8).unwrap()), Some(8));
+    assert_eq!(kmalloc.class_of(&Layout::from_size_align(2049, 8).unwrap()), None);
+
+    let kmalloc = Kmalloc::<32>::with_threshold(256);
+    assert_eq!(kmalloc.class_of(&Layout::from_size_align(256, 8).unwrap()), Some(5));
+    assert_eq!(kmalloc.class_of(&Layout::from_size_align(257, 8).unwrap()), None);
+}
+
+#[test]
+fn test_kmalloc_alloc_and_free() {
+    let mut kmalloc = Kmalloc::<32>::with_threshold(512);
+    let space = Box::leak(vec![0usize; 64 * 1024].into_boxed_slice());
+    unsafe {
+        let start = space.as_ptr() as usize;
+        kmalloc.free_heap(start, start + size_of_val(space));
+    }
+
+    let mut allocations = Vec::new();
+    for size in [1, 8, 24, 100, 512, 513, 4000] {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        allocations.push((kmalloc.alloc(layout).unwrap(), layout));
+    }
+    let in_use: usize = kmalloc.caches().iter().map(|cache| cache.stats_in_use()).sum();
+    assert_eq!(in_use, 5);
+
+    for (ptr, layout) in allocations {
+        unsafe { kmalloc.dealloc(ptr, layout) };
+    }
+    let in_use: usize = kmalloc.caches().iter().map(|cache| cache.stats_in_use()).sum();
+    assert_eq!(in_use, 0);
+    // 1和8字节共用同一个cache
+    assert_eq!(kmalloc.shrink(), 4);
+    assert_eq!(kmalloc.heap().stats_alloc_actual(), 0);
+}
+
+#[test]
+fn test_slab_heap_fragmentation() {
+    let mut slab = SlabHeap::<32>::new(64);
+    let space = Box::leak(vec![0usize; 16 * 1024].into_boxed_slice());
+    unsafe {
+        let start = space.as_ptr() as usize;
+        slab.free_heap(start, start + size_of_val(space));
+    }
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = slab.alloc(layout).unwrap();
+    let report = slab.fragmentation();
+    // 一个slab里只用了一个对象,其余都算内部浪费
+    assert_eq!(
+        report.internal_waste,
+        slab.cache().stats_slab_bytes() - slab.cache().stats_object_bytes()
+    );
+    assert_eq!(report.free, slab.heap().fragmentation().free);
+    assert_eq!(report.free, size_of_val(space) - slab.cache().stats_slab_bytes());
+
+    unsafe { slab.dealloc(ptr, layout) };
+    slab.shrink();
+    assert_eq!(slab.fragmentation().internal_waste, 0);
+}